@@ -0,0 +1,167 @@
+use regex::Regex;
+
+lazy_static! {
+    static ref ANNOTATION: Regex = Regex::new(r"\[[^\]]*\]").unwrap();
+    static ref SINGLE_D20: Regex =
+        Regex::new(r"^1?d20(?P<reroll>r[0-9]+)?(?P<rest>(\D.*)?)$").unwrap();
+    static ref DICE_TERM: Regex =
+        Regex::new(r"(?P<dice>[0-9]*d[0-9]+)(?P<ops>((rr|ro|ra|mi|ma|k|p|e)[hl<>]?[0-9]+)*)")
+            .unwrap();
+    static ref OPERATION: Regex =
+        Regex::new(r"(?P<op>rr|ro|ra|mi|ma|k|p|e)(?P<selector>[hl<>]?)(?P<num>[0-9]+)").unwrap();
+}
+
+/// The native modifiers of a single dice term, in the order native notation expects them.
+#[derive(Default)]
+struct Modifiers {
+    explode: Option<String>,
+    drop: Option<String>,
+    reroll: Option<String>,
+    keep: Option<String>,
+}
+
+/// Sets a modifier, rejecting a second one of the same kind.
+fn set(slot: &mut Option<String>, value: String) -> Result<(), &'static str> {
+    if slot.replace(value).is_some() {
+        return Err("Only one of each Avrae operation per dice term can be translated.");
+    }
+    Ok(())
+}
+
+/// Translates the operations of a single dice term, e.g. the `ro1kh1` of `2d20ro1kh1`.
+fn translate_operations(ops: &str) -> Result<String, &'static str> {
+    let mut modifiers = Modifiers::default();
+    for cap in OPERATION.captures_iter(ops) {
+        let num = &cap["num"];
+        match (&cap["op"], &cap["selector"]) {
+            ("k", "h") => set(&mut modifiers.keep, format!("h{}", num))?,
+            ("k", "l") => set(&mut modifiers.keep, format!("l{}", num))?,
+            ("k", _) => return Err("Only the kh and kl keep selectors can be translated."),
+            ("p", "h") => set(&mut modifiers.drop, format!("dh{}", num))?,
+            ("p", "l") => set(&mut modifiers.drop, format!("dl{}", num))?,
+            ("p", _) => return Err("Only the ph and pl drop selectors can be translated."),
+            // Native rerolls take every die at or below the number, so only rerolls of 1s
+            // and of dice below a number mean the same thing
+            ("ro", "") if num == "1" => set(&mut modifiers.reroll, String::from("r1"))?,
+            ("ro", "<") if num != "0" && num != "1" => {
+                let below = num.parse::<u32>().map_err(|_| "Failed to parse reroll.")? - 1;
+                set(&mut modifiers.reroll, format!("r{}", below))?
+            }
+            ("ro", _) => return Err("Only ro1 and ro<N rerolls can be translated."),
+            ("rr", _) => return Err("Recursive rerolls (rr) are not supported."),
+            ("e", "") => set(&mut modifiers.explode, format!("!{}", num))?,
+            ("e", selector @ ("<" | ">")) => {
+                set(&mut modifiers.explode, format!("!{}{}", selector, num))?
+            }
+            ("e", _) => return Err("Only e, e< and e> explosions can be translated."),
+            _ => return Err("Avrae ra, mi and ma operations are not supported."),
+        }
+    }
+    let Modifiers {
+        explode,
+        drop,
+        reroll,
+        keep,
+    } = modifiers;
+    Ok([explode, drop, reroll, keep]
+        .iter()
+        .flatten()
+        .cloned()
+        .collect())
+}
+
+/// Translates a single Avrae-style dice expression into native notation. Only the dice terms
+/// are rewritten, so the rest of the expression is kept as written.
+fn translate_expr(expr: &str) -> Result<String, &'static str> {
+    let mut translated = String::with_capacity(expr.len());
+    let mut last = 0;
+    for cap in DICE_TERM.captures_iter(expr) {
+        let whole = cap.get(0).unwrap();
+        translated.push_str(&expr[last..whole.start()]);
+        translated.push_str(&cap["dice"]);
+        translated.push_str(&translate_operations(&cap["ops"])?);
+        last = whole.end();
+    }
+    translated.push_str(&expr[last..]);
+    Ok(translated)
+}
+
+/// Applies advantage or disadvantage to an expression beginning with a single d20.
+fn apply_advantage(expr: &str, keep: &str) -> Result<String, &'static str> {
+    let cap = SINGLE_D20
+        .captures(expr)
+        .ok_or("Advantage and disadvantage require a single d20 roll.")?;
+    let reroll = cap.name("reroll").map_or("", |reroll| reroll.as_str());
+    let rest = cap.name("rest").map_or("", |rest| rest.as_str());
+    Ok(format!("2d20{}{}{}", reroll, keep, rest))
+}
+
+/// Translates Avrae-style arguments (e.g. `1d20ro1+5 [slashing] -rr 3 adv`) into a list of
+/// native roll expressions. Bracketed annotations are discarded.
+pub fn translate(args: impl Iterator<Item = String>) -> Result<Vec<String>, &'static str> {
+    let joined = args.collect::<Vec<_>>().join(" ");
+    let stripped = ANNOTATION.replace_all(&joined, " ");
+
+    let mut tokens = stripped.split_whitespace();
+    let mut exprs = vec![];
+    let mut repeat = 1;
+    let mut adv = false;
+    let mut dis = false;
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "-rr" => {
+                repeat = tokens
+                    .next()
+                    .ok_or("Missing count for -rr.")?
+                    .parse::<usize>()
+                    .map_err(|_| "Failed to parse count for -rr.")?;
+            }
+            "adv" => adv = true,
+            "dis" => dis = true,
+            expr => exprs.push(translate_expr(expr)?),
+        }
+    }
+
+    if exprs.is_empty() {
+        return Err("No roll specified.");
+    }
+
+    // Advantage and disadvantage cancel each other out
+    if adv != dis {
+        let keep = if adv { "h1" } else { "l1" };
+        exprs[0] = apply_advantage(&exprs[0], keep)?;
+    }
+
+    let mut repeated = Vec::with_capacity(exprs.len() * repeat);
+    for _ in 0..repeat {
+        repeated.extend(exprs.iter().cloned());
+    }
+
+    Ok(repeated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_only_dice_terms() {
+        assert_eq!(translate_expr("1d20ro1+5"), Ok(String::from("1d20r1+5")));
+        assert_eq!(translate_expr("4d6kh3"), Ok(String::from("4d6h3")));
+        assert_eq!(
+            translate_expr("2d20kl1+attack"),
+            Ok(String::from("2d20l1+attack"))
+        );
+        assert_eq!(translate_expr("8d6e6ph1"), Ok(String::from("8d6!6dh1")));
+        assert_eq!(translate_expr("3d6ro<3"), Ok(String::from("3d6r2")));
+    }
+
+    #[test]
+    fn rejects_operations_that_change_meaning() {
+        assert!(translate_expr("4d6k3").is_err());
+        assert!(translate_expr("1d20ro2").is_err());
+        assert!(translate_expr("1d20rr1").is_err());
+        assert!(translate_expr("4d6mi2").is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use std::fs;
+
+/// A target's damage resistances, vulnerabilities and immunities.
+#[derive(Clone, Debug, Default)]
+pub struct Defenses {
+    resistances: Vec<String>,
+    vulnerabilities: Vec<String>,
+    immunities: Vec<String>,
+}
+
+/// Parses a list of damage types such as `["fire", "cold"]` or `fire, cold`.
+fn parse_types(value: &str) -> Vec<String> {
+    value
+        .trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(',')
+        .map(|kind| kind.trim().trim_matches('"').to_lowercase())
+        .filter(|kind| !kind.is_empty())
+        .collect()
+}
+
+impl Defenses {
+    /// Loads defenses from a file of `key = [types]` lines, where the key is one of
+    /// `resistances`, `vulnerabilities` or `immunities`. Blank lines and `#` comments are
+    /// ignored, so simple TOML files can be used directly.
+    pub fn load(path: &str) -> Result<Defenses, &'static str> {
+        let contents = fs::read_to_string(path).map_err(|_| "Failed to read target file.")?;
+        let mut defenses = Defenses::default();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with('[') {
+                continue;
+            }
+            let mut iter = line.splitn(2, '=');
+            let key = iter.next().unwrap_or("").trim();
+            let value = iter
+                .next()
+                .ok_or("Target file lines must be of the form key = [types].")?;
+            let types = parse_types(value);
+            match key {
+                "resistances" => defenses.resistances.extend(types),
+                "vulnerabilities" => defenses.vulnerabilities.extend(types),
+                "immunities" => defenses.immunities.extend(types),
+                _ => return Err("Unknown key in target file."),
+            }
+        }
+        Ok(defenses)
+    }
+
+    /// Applies the defenses to an amount of damage of the given type, returning the damage
+    /// dealt and the defense that applied, if any.
+    pub fn apply(&self, kind: &str, amount: i32) -> (i32, Option<&'static str>) {
+        let has = |types: &[String]| types.iter().any(|t| t == kind);
+        if has(&self.immunities) {
+            (0, Some("immune"))
+        } else if has(&self.resistances) {
+            (amount / 2, Some("resistant"))
+        } else if has(&self.vulnerabilities) {
+            (amount.saturating_mul(2), Some("vulnerable"))
+        } else {
+            (amount, None)
+        }
+    }
+}
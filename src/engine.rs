@@ -0,0 +1,154 @@
+use crate::{
+    error::RollError,
+    expr::{Expr, ExprOutcome},
+};
+use rand::prelude::*;
+use std::sync::{Mutex, PoisonError};
+
+/// Bounds on the work a single evaluation may do, for expressions from untrusted sources.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// The most dice an expression may roll, not counting explosions.
+    pub max_dice: u32,
+    /// The most faces any one die may have.
+    pub max_faces: u32,
+    /// The most times a single die may explode; longer chains stop early.
+    pub max_explosions: usize,
+    /// The longest expression, in bytes, that will be parsed.
+    pub max_length: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_dice: 1_000,
+            max_faces: 1_000,
+            max_explosions: 20,
+            max_length: 500,
+        }
+    }
+}
+
+/// Evaluates roll expressions within resource limits, returning errors instead of panicking
+/// or running away on hostile input.
+pub struct Engine;
+
+/// Counts the dice an expression rolls, checking each die against the face limit.
+fn count_dice(expr: &Expr, limits: &Limits) -> Result<u32, RollError> {
+    match expr {
+        Expr::Roll(roll) => {
+            if roll.die() > limits.max_faces {
+                return Err(RollError::LimitExceeded("Dice have too many faces."));
+            }
+            Ok(roll.dice_rolled())
+        }
+        Expr::Symbols(roll) => Ok(roll.num()),
+        Expr::Group(members, _) => members.iter().try_fold(0u32, |total, member| {
+            Ok(total.saturating_add(count_dice(member, limits)?))
+        }),
+        Expr::Constant(_) => Ok(0),
+        Expr::Negate(expr) => count_dice(expr, limits),
+        Expr::Binary(lhs, _, rhs) => {
+            Ok(count_dice(lhs, limits)?.saturating_add(count_dice(rhs, limits)?))
+        }
+    }
+}
+
+impl Engine {
+    /// Parses an expression, rejecting it if it exceeds any of the limits.
+    pub fn parse(expr: &str, limits: &Limits) -> Result<Expr, RollError> {
+        if expr.len() > limits.max_length {
+            return Err(RollError::LimitExceeded("Expression is too long."));
+        }
+        let parsed: Expr = expr.parse()?;
+        if count_dice(&parsed, limits)? > limits.max_dice {
+            return Err(RollError::LimitExceeded("Expression rolls too many dice."));
+        }
+        Ok(parsed)
+    }
+
+    /// Parses and rolls an expression within the limits.
+    pub fn eval(expr: &str, limits: &Limits) -> Result<ExprOutcome, RollError> {
+        let parsed = Engine::parse(expr, limits)?;
+        Ok(parsed.roll_limited(&mut thread_rng(), limits.max_explosions))
+    }
+}
+
+/// An engine that owns its limits and random number generator, for servers that roll on many
+/// threads or tasks at once. It is `Send` and `Sync`, so one can be shared behind an `Arc`.
+pub struct SharedEngine {
+    limits: Limits,
+    rng: Mutex<StdRng>,
+}
+
+impl Default for SharedEngine {
+    fn default() -> SharedEngine {
+        SharedEngine::new(Limits::default())
+    }
+}
+
+impl SharedEngine {
+    /// Creates an engine with the given limits and a generator seeded from the system.
+    pub fn new(limits: Limits) -> SharedEngine {
+        SharedEngine {
+            limits,
+            rng: Mutex::new(StdRng::from_entropy()),
+        }
+    }
+
+    /// Creates an engine whose rolls are reproducible from the seed, as long as they are made
+    /// in the same order.
+    pub fn seeded(limits: Limits, seed: u64) -> SharedEngine {
+        SharedEngine {
+            limits,
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+
+    /// Returns the limits the engine enforces.
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Parses an expression, rejecting it if it exceeds any of the limits.
+    pub fn parse(&self, expr: &str) -> Result<Expr, RollError> {
+        Engine::parse(expr, &self.limits)
+    }
+
+    /// Rolls an expression that has already been parsed. The generator is only locked while
+    /// the dice are rolled.
+    pub fn roll(&self, expr: &Expr) -> ExprOutcome {
+        // A panic elsewhere cannot leave the generator in a bad state, so ignore poisoning
+        let mut rng = self.rng.lock().unwrap_or_else(PoisonError::into_inner);
+        expr.roll_limited(&mut *rng, self.limits.max_explosions)
+    }
+
+    /// Parses and rolls an expression within the limits.
+    pub fn eval(&self, expr: &str) -> Result<ExprOutcome, RollError> {
+        Ok(self.roll(&self.parse(expr)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_die_counts_toward_the_limit() {
+        let limits = Limits {
+            max_dice: 10,
+            ..Limits::default()
+        };
+        let too_many = |expr: &str| {
+            matches!(
+                Engine::parse(expr, &limits),
+                Err(RollError::LimitExceeded("Expression rolls too many dice."))
+            )
+        };
+        assert!(!too_many("5d66"));
+        assert!(too_many("6d66"));
+        assert!(too_many("4d666"));
+        assert!(!too_many("10d6"));
+        assert!(too_many("6d6+5d6"));
+    }
+}
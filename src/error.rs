@@ -0,0 +1,64 @@
+use std::{error, fmt};
+
+/// An error from parsing or evaluating dice notation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RollError {
+    /// The notation is malformed. `position` is the byte offset in `input` where the problem
+    /// was found.
+    Parse {
+        input: String,
+        position: usize,
+        reason: &'static str,
+    },
+    /// The notation is well formed but describes dice that cannot be rolled, such as a die
+    /// with no faces.
+    InvalidDice(&'static str),
+    /// An expression divides by a constant zero.
+    DivisionByZero,
+    /// The expression exceeds a numeric or resource limit.
+    LimitExceeded(&'static str),
+}
+
+impl fmt::Display for RollError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.reason())
+    }
+}
+
+impl error::Error for RollError {}
+
+/// Lets callers that report errors as plain messages keep using `?`.
+impl From<RollError> for &'static str {
+    fn from(error: RollError) -> &'static str {
+        error.reason()
+    }
+}
+
+impl RollError {
+    pub(crate) fn parse(input: &str, position: usize, reason: &'static str) -> RollError {
+        RollError::Parse {
+            input: input.to_string(),
+            position,
+            reason,
+        }
+    }
+
+    /// Returns a short description of the error.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            RollError::Parse { reason, .. } => reason,
+            RollError::InvalidDice(reason) | RollError::LimitExceeded(reason) => reason,
+            RollError::DivisionByZero => "Cannot divide by zero.",
+        }
+    }
+
+    /// Places a parse error found in part of a larger input at its position in the whole.
+    pub(crate) fn within(self, input: &str, offset: usize) -> RollError {
+        match self {
+            RollError::Parse {
+                position, reason, ..
+            } => RollError::parse(input, offset + position, reason),
+            error => error,
+        }
+    }
+}
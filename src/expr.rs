@@ -0,0 +1,1010 @@
+use crate::{
+    error::RollError,
+    roll::{self, Keep, Outcome, Roll, MAX_EXPLOSIONS},
+    symbols::{SymbolOutcome, SymbolRoll},
+};
+use rand::prelude::*;
+use regex::Regex;
+use std::{collections::BTreeMap, fmt, str};
+
+lazy_static! {
+    static ref GROUP_KEEP_REGEX: Regex =
+        Regex::new(r"^k?(?P<high_or_low>[hl])(?P<keep>[0-9]+)").unwrap();
+    static ref TAG_REGEX: Regex = Regex::new(r"^\[(?P<tag>[a-z][a-z_\-]*)\]").unwrap();
+}
+
+/// The precedence of expressions that never need parentheses, such as plain dice.
+const ATOM_PRECEDENCE: u8 = 4;
+
+/// The precedence of a negated expression.
+const NEGATE_PRECEDENCE: u8 = 3;
+
+/// The most pairs of totals combined when working out an exact distribution, beyond which
+/// the distribution is left unknown.
+const MAX_COMBINATIONS: usize = 1_000_000;
+
+/// An arithmetic operator joining two expressions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::Add => write!(f, "+"),
+            Op::Sub => write!(f, "-"),
+            Op::Mul => write!(f, "*"),
+            Op::Div => write!(f, "/"),
+        }
+    }
+}
+
+impl Op {
+    fn precedence(self) -> u8 {
+        match self {
+            Op::Add | Op::Sub => 1,
+            Op::Mul | Op::Div => 2,
+        }
+    }
+
+    /// Returns whether an operand of the given precedence must be parenthesized.
+    fn needs_parens(self, operand: u8, right: bool) -> bool {
+        operand < self.precedence() || (right && operand == self.precedence() && self != Op::Add)
+    }
+
+    /// Applies the operator. Division rounds toward zero, and dividing by zero gives zero.
+    fn apply(self, lhs: i32, rhs: i32) -> i32 {
+        match self {
+            Op::Add => lhs.saturating_add(rhs),
+            Op::Sub => lhs.saturating_sub(rhs),
+            Op::Mul => lhs.saturating_mul(rhs),
+            Op::Div => lhs.checked_div(rhs).unwrap_or(0),
+        }
+    }
+}
+
+/// Combines the distributions of two independent expressions joined by an operator, or
+/// returns `None` if there are too many pairs of totals or a divisor could be zero.
+fn combine(
+    lhs: &BTreeMap<i32, f64>,
+    op: Op,
+    rhs: &BTreeMap<i32, f64>,
+) -> Option<BTreeMap<i32, f64>> {
+    if lhs.len().saturating_mul(rhs.len()) > MAX_COMBINATIONS
+        || (op == Op::Div && rhs.contains_key(&0))
+    {
+        return None;
+    }
+    let mut chances = BTreeMap::new();
+    for (&a, &p) in lhs {
+        for (&b, &q) in rhs {
+            *chances.entry(op.apply(a, b)).or_insert(0.0) += p * q;
+        }
+    }
+    Some(chances)
+}
+
+/// Wraps text in parentheses if needed.
+fn parenthesize(text: String, needed: bool) -> String {
+    if needed {
+        format!("({})", text)
+    } else {
+        text
+    }
+}
+
+/// Negates the amounts of typed parts of a total.
+fn negated(parts: Vec<(Option<&str>, i32)>) -> Vec<(Option<&str>, i32)> {
+    parts
+        .into_iter()
+        .map(|(tag, amount)| (tag, amount.saturating_neg()))
+        .collect()
+}
+
+/// Formats a value, asking for color with the alternate flag if `color` is set.
+fn styled(value: &impl fmt::Display, color: bool) -> String {
+    if color {
+        format!("{:#}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// A parsed roll expression: standard dice, symbol dice, a group of expressions, or
+/// arithmetic over them.
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Roll(Roll),
+    Symbols(SymbolRoll),
+    Group(Vec<Expr>, Option<Keep>),
+    Constant(i32),
+    Negate(Box<Expr>),
+    Binary(Box<Expr>, Op, Box<Expr>),
+}
+
+/// The result of rolling an `Expr`.
+#[derive(Clone, Debug)]
+pub enum ExprOutcome {
+    Roll(Outcome),
+    Symbols(SymbolOutcome),
+    Group(Vec<ExprOutcome>, Vec<bool>),
+    Constant(i32),
+    Negate(Box<ExprOutcome>),
+    Binary(Box<ExprOutcome>, Op, Box<ExprOutcome>),
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Roll(roll) => write!(f, "{}", roll),
+            Expr::Symbols(roll) => write!(f, "{}", roll),
+            Expr::Group(members, keep) => {
+                let members: Vec<_> = members.iter().map(|member| member.to_string()).collect();
+                write!(f, "{{{}}}", members.join(", "))?;
+                match keep {
+                    Some(keep) => write!(f, "{}", keep),
+                    None => Ok(()),
+                }
+            }
+            Expr::Constant(n) => write!(f, "{}", n),
+            Expr::Negate(expr) => {
+                let needed = expr.precedence() < ATOM_PRECEDENCE;
+                write!(f, "-{}", parenthesize(expr.to_string(), needed))
+            }
+            Expr::Binary(lhs, op, rhs) => {
+                let lhs = parenthesize(lhs.to_string(), op.needs_parens(lhs.precedence(), false));
+                let rhs = parenthesize(rhs.to_string(), op.needs_parens(rhs.precedence(), true));
+                write!(f, "{}{}{}", lhs, op, rhs)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ExprOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExprOutcome::Roll(outcome) => write!(f, "{}", styled(outcome, f.alternate())),
+            ExprOutcome::Symbols(outcome) => write!(f, "{}", outcome),
+            ExprOutcome::Group(members, kept) => {
+                // Dropped members are shown in brackets
+                let members: Vec<_> = members
+                    .iter()
+                    .zip(kept)
+                    .map(|(member, &kept)| {
+                        if kept {
+                            styled(member, f.alternate())
+                        } else {
+                            format!("[{}]", member)
+                        }
+                    })
+                    .collect();
+                write!(f, "{} {{{}}}", self.total(), members.join(", "))
+            }
+            ExprOutcome::Constant(n) => write!(f, "{}", n),
+            ExprOutcome::Negate(_) | ExprOutcome::Binary(..) => {
+                write!(f, "{} = {}", self.total(), self.breakdown(f.alternate()))
+            }
+        }
+    }
+}
+
+impl ExprOutcome {
+    /// Computes the total value of the outcome, counting only kept group members. Symbol
+    /// dice have no numeric value and count as zero.
+    pub fn total(&self) -> i32 {
+        match self {
+            ExprOutcome::Roll(outcome) => outcome.total(),
+            ExprOutcome::Symbols(_) => 0,
+            ExprOutcome::Group(members, kept) => members
+                .iter()
+                .zip(kept)
+                .filter(|(_, &kept)| kept)
+                .fold(0i32, |total, (member, _)| {
+                    total.saturating_add(member.total())
+                }),
+            ExprOutcome::Constant(n) => *n,
+            ExprOutcome::Negate(outcome) => outcome.total().saturating_neg(),
+            ExprOutcome::Binary(lhs, op, rhs) => op.apply(lhs.total(), rhs.total()),
+        }
+    }
+
+    /// Counts how many dice landed on each value across every roll in the outcome, in
+    /// ascending order of value. Symbol dice are not counted.
+    pub fn tally(&self) -> Vec<(i32, usize)> {
+        let mut counts = BTreeMap::new();
+        self.count_faces(&mut counts);
+        counts.into_iter().collect()
+    }
+
+    /// Returns the sets of matching dice across every roll in the outcome as (width, height)
+    /// pairs, widest and then highest first.
+    pub fn sets(&self) -> Vec<(usize, i32)> {
+        roll::matched_sets(&self.tally())
+    }
+
+    /// Splits the total into the amounts of each damage type, in order of appearance. Added
+    /// and subtracted terms keep their own types; a product or quotient only has a type if
+    /// every typed roll in it shares that type. Untyped amounts are grouped under `None`.
+    pub fn damage_by_type(&self) -> Vec<(Option<&str>, i32)> {
+        let mut damage: Vec<(Option<&str>, i32)> = vec![];
+        for (tag, amount) in self.typed_parts() {
+            match damage.iter_mut().find(|(kind, _)| *kind == tag) {
+                Some((_, total)) => *total = total.saturating_add(amount),
+                None => damage.push((tag, amount)),
+            }
+        }
+        damage
+    }
+
+    fn typed_parts(&self) -> Vec<(Option<&str>, i32)> {
+        match self {
+            ExprOutcome::Roll(outcome) => vec![(outcome.tag(), outcome.total())],
+            ExprOutcome::Symbols(_) => vec![],
+            ExprOutcome::Group(members, kept) => members
+                .iter()
+                .zip(kept)
+                .filter(|(_, &kept)| kept)
+                .flat_map(|(member, _)| member.typed_parts())
+                .collect(),
+            ExprOutcome::Constant(n) => vec![(None, *n)],
+            ExprOutcome::Negate(outcome) => negated(outcome.typed_parts()),
+            ExprOutcome::Binary(lhs, Op::Add, rhs) => {
+                let mut parts = lhs.typed_parts();
+                parts.extend(rhs.typed_parts());
+                parts
+            }
+            ExprOutcome::Binary(lhs, Op::Sub, rhs) => {
+                let mut parts = lhs.typed_parts();
+                parts.extend(negated(rhs.typed_parts()));
+                parts
+            }
+            ExprOutcome::Binary(..) => {
+                let mut tags = vec![];
+                self.collect_tags(&mut tags);
+                tags.dedup();
+                let tag = match tags.as_slice() {
+                    [tag] => Some(*tag),
+                    _ => None,
+                };
+                vec![(tag, self.total())]
+            }
+        }
+    }
+
+    fn collect_tags<'a>(&'a self, tags: &mut Vec<&'a str>) {
+        match self {
+            ExprOutcome::Roll(outcome) => tags.extend(outcome.tag()),
+            ExprOutcome::Group(members, _) => {
+                for member in members {
+                    member.collect_tags(tags);
+                }
+            }
+            ExprOutcome::Negate(outcome) => outcome.collect_tags(tags),
+            ExprOutcome::Binary(lhs, _, rhs) => {
+                lhs.collect_tags(tags);
+                rhs.collect_tags(tags);
+            }
+            ExprOutcome::Symbols(_) | ExprOutcome::Constant(_) => {}
+        }
+    }
+
+    fn count_faces(&self, counts: &mut BTreeMap<i32, usize>) {
+        match self {
+            ExprOutcome::Roll(outcome) => {
+                for (value, count) in outcome.tally() {
+                    *counts.entry(value).or_insert(0) += count;
+                }
+            }
+            ExprOutcome::Group(members, _) => {
+                for member in members {
+                    member.count_faces(counts);
+                }
+            }
+            ExprOutcome::Negate(outcome) => outcome.count_faces(counts),
+            ExprOutcome::Binary(lhs, _, rhs) => {
+                lhs.count_faces(counts);
+                rhs.count_faces(counts);
+            }
+            ExprOutcome::Symbols(_) | ExprOutcome::Constant(_) => {}
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            ExprOutcome::Binary(_, op, _) => op.precedence(),
+            ExprOutcome::Negate(_) => NEGATE_PRECEDENCE,
+            _ => ATOM_PRECEDENCE,
+        }
+    }
+
+    /// Shows how the outcome of an arithmetic expression was reached from its parts, in color
+    /// if `color` is set.
+    fn breakdown(&self, color: bool) -> String {
+        match self {
+            ExprOutcome::Roll(outcome) => {
+                parenthesize(styled(outcome, color), outcome.has_modifier())
+            }
+            ExprOutcome::Constant(n) => parenthesize(n.to_string(), *n < 0),
+            ExprOutcome::Negate(outcome) => {
+                let needed = outcome.precedence() < ATOM_PRECEDENCE;
+                format!("-{}", parenthesize(outcome.breakdown(color), needed))
+            }
+            ExprOutcome::Binary(lhs, op, rhs) => {
+                let lhs = parenthesize(
+                    lhs.breakdown(color),
+                    op.needs_parens(lhs.precedence(), false),
+                );
+                let rhs = parenthesize(
+                    rhs.breakdown(color),
+                    op.needs_parens(rhs.precedence(), true),
+                );
+                format!("{} {} {}", lhs, op, rhs)
+            }
+            _ => styled(self, color),
+        }
+    }
+}
+
+/// Splits the inside of a group on commas that are not nested in another group or function,
+/// returning each member with its offset.
+fn split_members(input: &str) -> Result<Vec<(usize, &str)>, usize> {
+    let mut members = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in input.char_indices() {
+        match c {
+            '{' | '(' => depth += 1,
+            '}' | ')' => {
+                if depth == 0 {
+                    return Err(i);
+                }
+                depth -= 1;
+            }
+            ',' if depth == 0 => {
+                members.push((start, &input[start..i]));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(input.len());
+    }
+    members.push((start, &input[start..]));
+    Ok(members)
+}
+
+/// A recursive descent parser for arithmetic over dice, following the grammar
+///
+/// ```text
+/// sum     = product (("+" | "-") product)*
+/// product = unary (("*" | "/") unary)*
+/// unary   = "-" unary | atom
+/// atom    = dice ("[" type "]")? | number | "(" sum ")" | group | ("best" | "worst") "(" members ")"
+/// ```
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    /// Reports a parse error at the current position.
+    fn error(&self, reason: &'static str) -> RollError {
+        RollError::parse(self.input, self.pos, reason)
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.pos = self.input.len() - self.rest().trim_start().len();
+    }
+
+    /// Consumes the given character if it comes next.
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expr, RollError> {
+        let mut expr = self.product()?;
+        loop {
+            let op = if self.eat('+') {
+                Op::Add
+            } else if self.eat('-') {
+                Op::Sub
+            } else {
+                return Ok(expr);
+            };
+            expr = Expr::binary(expr, op, self.product()?)?;
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, RollError> {
+        let mut expr = self.unary()?;
+        loop {
+            let op = if self.eat('*') {
+                Op::Mul
+            } else if self.eat('/') {
+                Op::Div
+            } else {
+                return Ok(expr);
+            };
+            expr = Expr::binary(expr, op, self.unary()?)?;
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, RollError> {
+        if !self.eat('-') {
+            return self.atom();
+        }
+        Ok(match self.unary()? {
+            Expr::Constant(n) => Expr::Constant(-n),
+            expr => Expr::Negate(Box::new(expr)),
+        })
+    }
+
+    fn atom(&mut self) -> Result<Expr, RollError> {
+        self.skip_whitespace();
+        let rest = self.rest();
+
+        if self.eat('(') {
+            let expr = self.sum()?;
+            if !self.eat(')') {
+                return Err(self.error("Unbalanced parentheses."));
+            }
+            return Ok(expr);
+        }
+
+        // `best(a, b)` and `worst(a, b)` keep a single member of a group
+        for (name, keep) in [("best(", Keep::High(1)), ("worst(", Keep::Low(1))] {
+            if rest.starts_with(name) {
+                self.pos += name.len() - 1;
+                let members = self.members()?;
+                return Ok(Expr::Group(members, Some(keep)));
+            }
+        }
+
+        if rest.starts_with('{') {
+            let members = self.members()?;
+            let keep = match GROUP_KEEP_REGEX.captures(self.rest()) {
+                Some(cap) => {
+                    let n = cap["keep"].parse::<usize>().map_err(|_| {
+                        self.error("Error parsing number of group members to keep.")
+                    })?;
+                    if n > members.len() {
+                        return Err(RollError::InvalidDice(
+                            "Cannot keep more group members than the group contains.",
+                        ));
+                    }
+                    self.pos += cap[0].len();
+                    match &cap["high_or_low"] {
+                        "h" => Some(Keep::High(n)),
+                        _ => Some(Keep::Low(n)),
+                    }
+                }
+                None => None,
+            };
+            return Ok(Expr::Group(members, keep));
+        }
+
+        if let Some((mut roll, len)) =
+            Roll::parse_prefix(rest).map_err(|error| error.within(self.input, self.pos))?
+        {
+            self.pos += len;
+            // A damage type may follow the dice, as in `2d6[fire]`
+            if let Some(cap) = TAG_REGEX.captures(self.rest()) {
+                roll.set_tag(&cap["tag"]);
+                self.pos += cap[0].len();
+            }
+            return Ok(Expr::Roll(roll));
+        }
+
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits > 0 {
+            let n = rest[..digits]
+                .parse::<i32>()
+                .map_err(|_| self.error("Constant is too large."))?;
+            self.pos += digits;
+            return Ok(Expr::Constant(n));
+        }
+
+        Err(self.error("Invalid roll notation."))
+    }
+
+    /// Consumes a group enclosed in matching braces or parentheses and parses its members.
+    fn members(&mut self) -> Result<Vec<Expr>, RollError> {
+        let start = self.pos + 1;
+        let mut open = vec![];
+        let mut end = None;
+        for (i, c) in self.rest().char_indices() {
+            match c {
+                '{' => open.push('}'),
+                '(' => open.push(')'),
+                '}' | ')' => {
+                    // Each bracket must be closed by its own kind, so `{1d6)` is rejected
+                    if open.pop() != Some(c) {
+                        return Err(RollError::parse(
+                            self.input,
+                            self.pos + i,
+                            "Mismatched brackets in group.",
+                        ));
+                    }
+                    if open.is_empty() {
+                        end = Some(self.pos + i);
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let end = end.ok_or_else(|| {
+            RollError::parse(self.input, self.input.len(), "Unbalanced braces in group.")
+        })?;
+
+        let inner = &self.input[start..end];
+        let members = split_members(inner).map_err(|offset| {
+            RollError::parse(self.input, start + offset, "Unbalanced braces in group.")
+        })?;
+        let members = members
+            .into_iter()
+            .map(|(offset, member)| {
+                let leading = member.len() - member.trim_start().len();
+                member
+                    .parse()
+                    .map_err(|error: RollError| error.within(self.input, start + offset + leading))
+            })
+            .collect::<Result<_, _>>()?;
+        self.pos = end + 1;
+        Ok(members)
+    }
+}
+
+impl str::FromStr for Expr {
+    type Err = RollError;
+
+    fn from_str(input: &str) -> Result<Expr, Self::Err> {
+        let input = input.trim();
+        if SymbolRoll::matches(input) {
+            return Ok(Expr::Symbols(input.parse()?));
+        }
+
+        let mut parser = Parser { input, pos: 0 };
+        let expr = parser.sum()?;
+        parser.skip_whitespace();
+        if !parser.rest().is_empty() {
+            return Err(parser.error("Invalid roll notation."));
+        }
+        Ok(expr)
+    }
+}
+
+impl Expr {
+    /// Joins two expressions with an operator, folding a constant added to or subtracted
+    /// from dice into the roll's modifier so that `2d6+3` stays a single roll.
+    fn binary(lhs: Expr, op: Op, rhs: Expr) -> Result<Expr, RollError> {
+        match (lhs, op, rhs) {
+            (Expr::Roll(mut roll), Op::Add | Op::Sub, Expr::Constant(n)) => {
+                let n = if op == Op::Sub {
+                    n.checked_neg()
+                        .ok_or(RollError::LimitExceeded("Modifier is too large."))?
+                } else {
+                    n
+                };
+                roll.add_modifier(n)?;
+                Ok(Expr::Roll(roll))
+            }
+            (_, Op::Div, Expr::Constant(0)) => Err(RollError::DivisionByZero),
+            (lhs, op, rhs) => Ok(Expr::Binary(Box::new(lhs), op, Box::new(rhs))),
+        }
+    }
+
+    /// Returns a canonical form of the expression that rolls the same way, folding constants
+    /// into rolls and combining rolls of the same dice, so `2d20kh1 + 3 + 2` becomes
+    /// `2d20h1+5` and `1d6+1d6` becomes `2d6`.
+    pub fn canonical(&self) -> Expr {
+        match self {
+            Expr::Group(members, keep) => {
+                Expr::Group(members.iter().map(Expr::canonical).collect(), keep.clone())
+            }
+            Expr::Negate(expr) => match expr.canonical() {
+                Expr::Constant(n) => Expr::Constant(n.saturating_neg()),
+                Expr::Negate(inner) => *inner,
+                expr => Expr::Negate(Box::new(expr)),
+            },
+            Expr::Binary(lhs, op, rhs) => Expr::fold(lhs.canonical(), *op, rhs.canonical()),
+            _ => self.clone(),
+        }
+    }
+
+    /// Joins two canonical expressions with an operator, folding them into one where the
+    /// result rolls the same way.
+    fn fold(lhs: Expr, op: Op, rhs: Expr) -> Expr {
+        let (lhs, rhs) = match (lhs, op, rhs) {
+            (Expr::Constant(a), op, Expr::Constant(b)) if !(op == Op::Div && b == 0) => {
+                return Expr::Constant(op.apply(a, b));
+            }
+            // Constants go after dice, as in `d20+3`
+            (Expr::Constant(n), Op::Add, roll @ Expr::Roll(_)) => (roll, Expr::Constant(n)),
+            (Expr::Roll(a), Op::Add, Expr::Roll(b)) => match a.merge(&b) {
+                Some(merged) => return Expr::Roll(merged),
+                None => (Expr::Roll(a), Expr::Roll(b)),
+            },
+            (lhs, _, rhs) => (lhs, rhs),
+        };
+        Expr::binary(lhs.clone(), op, rhs.clone())
+            .unwrap_or_else(|_| Expr::Binary(Box::new(lhs), op, Box::new(rhs)))
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary(_, op, _) => op.precedence(),
+            Expr::Roll(roll) if roll.has_modifier() => Op::Add.precedence(),
+            Expr::Negate(_) => NEGATE_PRECEDENCE,
+            Expr::Constant(n) if *n < 0 => NEGATE_PRECEDENCE,
+            _ => ATOM_PRECEDENCE,
+        }
+    }
+
+    /// Returns whether the expression produces a meaningful numeric total.
+    pub fn is_numeric(&self) -> bool {
+        !matches!(self, Expr::Symbols(_))
+    }
+
+    /// Computes the exact chance of each total, if it can be worked out. Kept groups, symbol
+    /// dice and expressions with too many combinations of totals have none.
+    pub fn distribution(&self) -> Option<BTreeMap<i32, f64>> {
+        match self {
+            Expr::Roll(roll) => Some(roll.distribution()),
+            Expr::Symbols(_) | Expr::Group(_, Some(_)) => None,
+            Expr::Group(members, None) => {
+                let start: BTreeMap<i32, f64> = [(0, 1.0)].iter().copied().collect();
+                members.iter().try_fold(start, |chances, member| {
+                    combine(&chances, Op::Add, &member.distribution()?)
+                })
+            }
+            Expr::Constant(n) => Some([(*n, 1.0)].iter().copied().collect()),
+            Expr::Negate(expr) => Some(
+                expr.distribution()?
+                    .into_iter()
+                    .map(|(total, chance)| (total.saturating_neg(), chance))
+                    .collect(),
+            ),
+            Expr::Binary(lhs, op, rhs) => combine(&lhs.distribution()?, *op, &rhs.distribution()?),
+        }
+    }
+
+    /// Computes the expected total, if it can be determined from the member expectations.
+    /// Products of independent rolls multiply their expectations, but integer division does
+    /// not, so its expected total is unknown.
+    pub fn expected_total(&self) -> Option<f64> {
+        match self {
+            Expr::Roll(roll) => Some(roll.expected_total()),
+            Expr::Symbols(_) => None,
+            Expr::Group(members, None) => {
+                members.iter().map(|member| member.expected_total()).sum()
+            }
+            Expr::Group(_, Some(_)) => None,
+            Expr::Constant(n) => Some(*n as f64),
+            Expr::Negate(expr) => expr.expected_total().map(|expected| -expected),
+            Expr::Binary(lhs, op, rhs) => {
+                let (lhs, rhs) = (lhs.expected_total()?, rhs.expected_total()?);
+                match op {
+                    Op::Add => Some(lhs + rhs),
+                    Op::Sub => Some(lhs - rhs),
+                    Op::Mul => Some(lhs * rhs),
+                    Op::Div => None,
+                }
+            }
+        }
+    }
+
+    /// Computes the variance of the expression's total, if it is known. Parts of the
+    /// expression are rolled independently, so their variances combine directly; division
+    /// and groups that keep members are not supported.
+    pub fn variance(&self) -> Option<f64> {
+        match self {
+            Expr::Roll(roll) => Some(roll.variance()),
+            Expr::Symbols(_) | Expr::Group(_, Some(_)) => None,
+            Expr::Group(members, None) => members.iter().map(|member| member.variance()).sum(),
+            Expr::Constant(_) => Some(0.0),
+            Expr::Negate(expr) => expr.variance(),
+            Expr::Binary(lhs, op, rhs) => {
+                let (lhs_var, rhs_var) = (lhs.variance()?, rhs.variance()?);
+                match op {
+                    Op::Add | Op::Sub => Some(lhs_var + rhs_var),
+                    Op::Mul => {
+                        let (lhs_mean, rhs_mean) = (lhs.expected_total()?, rhs.expected_total()?);
+                        Some(
+                            lhs_var * rhs_var
+                                + lhs_var * rhs_mean.powi(2)
+                                + rhs_var * lhs_mean.powi(2),
+                        )
+                    }
+                    Op::Div => None,
+                }
+            }
+        }
+    }
+
+    /// Computes the standard deviation of the expression's total, if it is known.
+    pub fn standard_deviation(&self) -> Option<f64> {
+        self.variance().map(f64::sqrt)
+    }
+
+    /// Returns this expression with the dice of every roll doubled, as for a critical hit.
+    pub fn critical(&self) -> Expr {
+        match self {
+            Expr::Roll(roll) => Expr::Roll(roll.critical()),
+            Expr::Symbols(_) | Expr::Constant(_) => self.clone(),
+            Expr::Group(members, keep) => Expr::Group(
+                members.iter().map(|member| member.critical()).collect(),
+                keep.clone(),
+            ),
+            Expr::Negate(expr) => Expr::Negate(Box::new(expr.critical())),
+            Expr::Binary(lhs, op, rhs) => {
+                Expr::Binary(Box::new(lhs.critical()), *op, Box::new(rhs.critical()))
+            }
+        }
+    }
+
+    /// Rolls the expression as the damage of an attack whose d20 showed `natural`. A natural 20
+    /// is a critical hit that doubles the damage dice, and a natural 1 always misses, giving
+    /// `None`.
+    pub fn roll_damage(&self, natural: i32, rng: &mut impl Rng) -> Option<ExprOutcome> {
+        match natural {
+            20 => Some(self.critical().roll(rng)),
+            1 => None,
+            _ => Some(self.roll(rng)),
+        }
+    }
+
+    /// Rolls the expression using the given random number generator.
+    pub fn roll(&self, rng: &mut impl Rng) -> ExprOutcome {
+        self.roll_limited(rng, MAX_EXPLOSIONS)
+    }
+
+    /// Rolls the expression, allowing each die to explode at most `max_explosions` times.
+    pub fn roll_limited(&self, rng: &mut impl Rng, max_explosions: usize) -> ExprOutcome {
+        self.evaluate(rng, &mut |roll, rng| roll.roll_limited(rng, max_explosions))
+    }
+
+    /// Rolls the expression, taking `value` on every d20 instead of rolling it.
+    pub fn take(&self, value: u32, rng: &mut impl Rng) -> ExprOutcome {
+        self.take_limited(value, rng, MAX_EXPLOSIONS)
+    }
+
+    /// Takes `value` on every d20 as `take` does, allowing other dice to explode at most
+    /// `max_explosions` times.
+    pub fn take_limited(
+        &self,
+        value: u32,
+        rng: &mut impl Rng,
+        max_explosions: usize,
+    ) -> ExprOutcome {
+        self.evaluate(rng, &mut |roll, rng| {
+            roll.take_limited(value, rng, max_explosions)
+        })
+    }
+
+    /// Returns warnings about parts of the expression that are valid but probably mistakes.
+    pub fn lint(&self) -> Vec<String> {
+        let mut warnings = vec![];
+        if !self.has_dice() {
+            warnings.push(format!("{} rolls no dice", self));
+        }
+        self.lint_into(&mut warnings);
+        warnings
+    }
+
+    fn lint_into(&self, warnings: &mut Vec<String>) {
+        match self {
+            Expr::Roll(roll) => warnings.extend(roll.lint()),
+            Expr::Symbols(_) | Expr::Constant(_) => {}
+            Expr::Group(members, _) => {
+                for member in members {
+                    member.lint_into(warnings);
+                }
+            }
+            Expr::Negate(expr) => expr.lint_into(warnings),
+            Expr::Binary(lhs, op, rhs) => {
+                if *op == Op::Mul && matches!(**rhs, Expr::Constant(0)) {
+                    warnings.push(format!("{} multiplies by zero", self));
+                }
+                lhs.lint_into(warnings);
+                rhs.lint_into(warnings);
+            }
+        }
+    }
+
+    /// Returns whether the expression rolls any dice.
+    fn has_dice(&self) -> bool {
+        match self {
+            Expr::Roll(_) | Expr::Symbols(_) => true,
+            Expr::Constant(_) => false,
+            Expr::Group(members, _) => members.iter().any(Expr::has_dice),
+            Expr::Negate(expr) => expr.has_dice(),
+            Expr::Binary(lhs, _, rhs) => lhs.has_dice() || rhs.has_dice(),
+        }
+    }
+
+    /// Computes the chance of a natural 20 on the expression's roll of d20s, as for a critical
+    /// hit. Only expressions with exactly one such roll, outside any kept group, have one.
+    pub fn crit_chance(&self) -> Option<f64> {
+        match self.d20_rolls()?.as_slice() {
+            [roll] => roll.crit_chance(),
+            _ => None,
+        }
+    }
+
+    /// Returns the plain d20 rolls of the expression, or `None` if a kept group holds any.
+    fn d20_rolls(&self) -> Option<Vec<&Roll>> {
+        match self {
+            Expr::Roll(roll) if roll.is_d20() => Some(vec![roll]),
+            Expr::Group(_, Some(_)) if self.has_d20() => None,
+            Expr::Group(members, None) => {
+                let mut rolls = vec![];
+                for member in members {
+                    rolls.extend(member.d20_rolls()?);
+                }
+                Some(rolls)
+            }
+            Expr::Negate(expr) => expr.d20_rolls(),
+            Expr::Binary(lhs, _, rhs) => {
+                let mut rolls = lhs.d20_rolls()?;
+                rolls.extend(rhs.d20_rolls()?);
+                Some(rolls)
+            }
+            _ => Some(vec![]),
+        }
+    }
+
+    /// Returns whether the expression contains a plain d20 roll.
+    pub fn has_d20(&self) -> bool {
+        match self {
+            Expr::Roll(roll) => roll.is_d20(),
+            Expr::Symbols(_) | Expr::Constant(_) => false,
+            Expr::Group(members, _) => members.iter().any(Expr::has_d20),
+            Expr::Negate(expr) => expr.has_d20(),
+            Expr::Binary(lhs, _, rhs) => lhs.has_d20() || rhs.has_d20(),
+        }
+    }
+
+    /// Rolls the expression, taking `value` on the first d20 roll only, as when a portent
+    /// replaces a roll.
+    pub fn take_first(&self, value: u32, rng: &mut impl Rng) -> ExprOutcome {
+        self.take_first_limited(value, rng, MAX_EXPLOSIONS)
+    }
+
+    /// Takes `value` on the first d20 roll as `take_first` does, allowing dice to explode at
+    /// most `max_explosions` times.
+    pub fn take_first_limited(
+        &self,
+        value: u32,
+        rng: &mut impl Rng,
+        max_explosions: usize,
+    ) -> ExprOutcome {
+        let mut pending = true;
+        self.evaluate(rng, &mut |roll, rng| {
+            if pending && roll.is_d20() {
+                pending = false;
+                roll.take_limited(value, rng, max_explosions)
+            } else {
+                roll.roll_limited(rng, max_explosions)
+            }
+        })
+    }
+
+    /// Evaluates the expression, producing the outcome of each standard roll with `roll`.
+    fn evaluate<R: Rng>(
+        &self,
+        rng: &mut R,
+        roll: &mut impl FnMut(&Roll, &mut R) -> Outcome,
+    ) -> ExprOutcome {
+        match self {
+            Expr::Roll(inner) => ExprOutcome::Roll(roll(inner, rng)),
+            Expr::Symbols(symbols) => ExprOutcome::Symbols(symbols.roll(&mut *rng)),
+            Expr::Group(members, keep) => {
+                let outcomes: Vec<_> = members
+                    .iter()
+                    .map(|member| member.evaluate(rng, roll))
+                    .collect();
+
+                // Rank members by subtotal to decide which are kept
+                let mut order: Vec<_> = (0..outcomes.len()).collect();
+                order.sort_by_key(|&i| outcomes[i].total());
+                let kept_indices = match keep {
+                    Some(keep) => &order[keep.range(order.len())],
+                    None => &order[..],
+                };
+                let mut kept = vec![false; outcomes.len()];
+                for &i in kept_indices {
+                    kept[i] = true;
+                }
+
+                ExprOutcome::Group(outcomes, kept)
+            }
+            Expr::Constant(n) => ExprOutcome::Constant(*n),
+            Expr::Negate(expr) => ExprOutcome::Negate(Box::new(expr.evaluate(rng, roll))),
+            Expr::Binary(lhs, op, rhs) => ExprOutcome::Binary(
+                Box::new(lhs.evaluate(rng, roll)),
+                *op,
+                Box::new(rhs.evaluate(rng, roll)),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn critical_does_not_overflow() {
+        let expr: Expr = "3000000000d6+2d8".parse().unwrap();
+        assert_eq!(expr.critical().to_string(), "4294967295d6+4d8");
+    }
+
+    #[test]
+    fn damage_doubles_on_a_natural_20() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let damage: Expr = "2d6+3".parse().unwrap();
+        assert!(damage.roll_damage(1, &mut rng).is_none());
+        let hit = damage.roll_damage(12, &mut rng).unwrap();
+        assert!((5..=15).contains(&hit.total()));
+        let crit = damage.roll_damage(20, &mut rng).unwrap();
+        assert!((7..=27).contains(&crit.total()));
+        assert_eq!(crit.to_string().matches(',').count(), 3);
+    }
+
+    #[test]
+    fn group_brackets_must_match() {
+        for (input, position) in [("{1d6)", 4), ("(1d6}", 4), ("{(1d6},1d4)", 5)] {
+            match input.parse::<Expr>() {
+                Err(RollError::Parse { position: p, .. }) => assert_eq!(p, position, "{}", input),
+                parsed => panic!("{} parsed as {:?}", input, parsed),
+            }
+        }
+        assert!("{(1d6),1d4}h1".parse::<Expr>().is_ok());
+    }
+
+    #[test]
+    fn crit_chance_of_expressions() {
+        let crit = |input: &str| input.parse::<Expr>().unwrap().crit_chance();
+        assert!((crit("1d20+1d4+5").unwrap() - 0.05).abs() < 1e-9);
+        assert!((crit("d20aa+5").unwrap() - 0.142625).abs() < 1e-9);
+        assert_eq!(crit("2d6"), None);
+        assert_eq!(crit("d20+d20"), None);
+        assert_eq!(crit("{1d20+5,1d20+3}h1"), None);
+    }
+
+    #[test]
+    fn damage_is_summed_by_type() {
+        let expr: Expr = "2d6[fire]+1d4[cold]".parse().unwrap();
+        assert_eq!(expr.to_string(), "2d6[fire]+d4[cold]");
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let outcome = expr.roll(&mut rng);
+            let damage = outcome.damage_by_type();
+            assert_eq!(damage.len(), 2);
+            let (fire, cold) = (damage[0], damage[1]);
+            assert_eq!(fire.0, Some("fire"));
+            assert!((2..=12).contains(&fire.1));
+            assert_eq!(cold.0, Some("cold"));
+            assert!((1..=4).contains(&cold.1));
+            assert_eq!(fire.1 + cold.1, outcome.total());
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use crate::sim;
+use rand::prelude::*;
+use roll::Expr;
+
+/// The length of the bar of the most likely total.
+const BAR_WIDTH: usize = 50;
+
+/// Totals less likely than this are left out of the chart.
+const MIN_PROBABILITY: f64 = 0.0001;
+
+/// Prints a bar chart of the chance of each total of an expression.
+pub fn run(expr: &Expr, samples: Option<usize>, rng: impl Rng) -> Result<(), &'static str> {
+    let (chances, source) = sim::chances(expr, samples, rng)?;
+    let chances: Vec<_> = chances
+        .into_iter()
+        .filter(|&(_, chance)| chance >= MIN_PROBABILITY)
+        .collect();
+    let most = chances
+        .iter()
+        .map(|&(_, chance)| chance)
+        .fold(0.0, f64::max);
+    let width = chances
+        .iter()
+        .map(|(total, _)| total.to_string().len())
+        .max()
+        .unwrap_or(0);
+
+    match (expr.expected_total(), expr.standard_deviation()) {
+        (Some(mean), Some(deviation)) => println!(
+            "{} ({}): mean {:.2}, standard deviation {:.2}",
+            expr, source, mean, deviation
+        ),
+        _ => println!("{} ({})", expr, source),
+    }
+    for (total, chance) in chances {
+        let bar = (chance / most * BAR_WIDTH as f64).round() as usize;
+        let line = format!(
+            "  {:>width$} {:>6.2}% {}",
+            total,
+            chance * 100.0,
+            "#".repeat(bar),
+            width = width
+        );
+        println!("{}", line.trim_end());
+    }
+    Ok(())
+}
@@ -0,0 +1,28 @@
+//! Dice rolling for tabletop games.
+//!
+//! Parse dice notation into a [`Roll`] or a full [`Expr`], then roll it with any random number
+//! generator:
+//!
+//! ```
+//! use rand::thread_rng;
+//! use roll::Roll;
+//!
+//! let roll: Roll = "4d6h3+2".parse().unwrap();
+//! let outcome = roll.roll(thread_rng());
+//! assert!((5..=20).contains(&outcome.total()));
+//! ```
+
+#[macro_use]
+extern crate lazy_static;
+
+pub mod engine;
+pub mod error;
+pub mod expr;
+pub mod roll;
+pub mod symbols;
+
+pub use crate::engine::{Engine, Limits, SharedEngine};
+pub use crate::error::RollError;
+pub use crate::expr::{Expr, ExprOutcome, Op};
+pub use crate::roll::{DieRoll, Explode, Keep, Outcome, ResultKind, Roll, Target};
+pub use crate::symbols::{SymbolOutcome, SymbolRoll};
@@ -1,16 +1,43 @@
-mod roll;
+mod avrae;
+mod damage;
+mod hist;
+mod npc;
+mod pointbuy;
+mod result_map;
+mod rolloff;
+mod routine;
+mod scatter;
+mod shop;
+mod sim;
+mod statblock;
+mod stats;
+mod tiers;
+use damage::Defenses;
+use npc::Npc;
 use rand::prelude::*;
-use roll::{Keep, Roll};
+use regex::Regex;
+use result_map::ResultMap;
+use roll::{roll::MAX_EXPLOSIONS, Expr, ExprOutcome, Keep, Op, Roll, RollError};
 use std::{
-    collections::HashMap,
-    env,
-    fs::File,
-    io::{self, BufRead, BufReader},
+    collections::{BTreeMap, HashMap},
+    env, fs,
+    io::{self, BufRead, IsTerminal, Write},
+    iter::{self, Peekable},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
 };
+use tiers::Tiers;
+use tracing::{debug, debug_span, warn};
+use tracing_subscriber::EnvFilter;
 
 #[macro_use]
 extern crate lazy_static;
 
+/// The most times a single expression can be repeated with `Nx`.
+const MAX_REPEAT: usize = 100;
+
 lazy_static! {
     static ref MACROS: HashMap<String, Vec<Roll>> = {
         let mut map = HashMap::new();
@@ -37,73 +64,1704 @@ lazy_static! {
 
         map
     };
+    static ref CONSTANT_REGEX: Regex =
+        Regex::new(r"(?P<sign>[\+\-])(?P<name>[A-Za-z_][A-Za-z0-9_]*)").unwrap();
+    static ref DIE_NAME_REGEX: Regex = Regex::new(r"^d([0-9]+|f)").unwrap();
+    static ref TAG_REGEX: Regex =
+        Regex::new(r"^(?P<expr>.*[^d])\[(?P<tag>[a-z][a-z_\-]*)\]$").unwrap();
+    static ref REPEAT_REGEX: Regex = Regex::new(r"^(?P<count>[0-9]+)x(?P<expr>.+)$").unwrap();
+    static ref SIGN_REGEX: Regex = Regex::new(r"\s*(?P<sign>[\+\-\*/])\s*").unwrap();
+    static ref INLINE_REGEX: Regex = Regex::new(r"\[\[(?P<expr>.*?)\]\]").unwrap();
+    static ref GROUP_OPEN_REGEX: Regex = Regex::new(r"(?P<delim>[\{\(,:])\s+").unwrap();
+    static ref GROUP_CLOSE_REGEX: Regex = Regex::new(r"\s+(?P<delim>[\}\),])").unwrap();
+    static ref SUFFIX_REGEX: Regex = Regex::new(r"\s+(?P<suffix>[hlr][0-9]|\[|each\])").unwrap();
+}
+
+/// Describes an error, showing the input with a caret under the offending character when
+/// the error comes from malformed notation.
+fn point_at(error: &RollError) -> String {
+    match error {
+        RollError::Parse {
+            input,
+            position,
+            reason,
+        } => {
+            let indent = "    ";
+            let column = input[..*position].chars().count();
+            format!(
+                "{}\n{}{}\n{}{}^",
+                reason,
+                indent,
+                input,
+                indent,
+                " ".repeat(column)
+            )
+        }
+        error => error.to_string(),
+    }
+}
+
+/// Normalizes a span of input outside of quotes.
+fn normalize_span(span: &str) -> String {
+    let span = span.to_lowercase();
+    let span = SIGN_REGEX.replace_all(&span, "$sign");
+    let span = GROUP_OPEN_REGEX.replace_all(&span, "$delim");
+    let span = GROUP_CLOSE_REGEX.replace_all(&span, "$delim");
+    SUFFIX_REGEX.replace_all(&span, "$suffix").into_owned()
+}
+
+/// Normalizes loosely written input such as `2D6 + 3` or `4d6 h3` into canonical tokens.
+/// Quoted text, such as the labels of a result map, is kept exactly as written.
+fn normalize(args: Vec<String>) -> Vec<String> {
+    let joined = args.join(" ");
+    let spans: Vec<&str> = joined.split('"').collect();
+    let mut normalized = String::with_capacity(joined.len());
+    for (i, span) in spans.iter().enumerate() {
+        if i % 2 == 0 {
+            normalized.push_str(&normalize_span(span));
+        } else {
+            // Only close the quote if the input did
+            normalized.push('"');
+            normalized.push_str(span);
+            if i + 1 < spans.len() {
+                normalized.push('"');
+            }
+        }
+    }
+
+    // Split on whitespace outside of quotes
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut quoted = false;
+    for c in normalized.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                token.push(c);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    debug!(?tokens, "normalized input");
+    tokens
+}
+
+/// Returns whether output to stdout should be colored, which is only when it is a terminal
+/// and `NO_COLOR` is not set.
+fn stdout_color() -> bool {
+    io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+/// Returns whether two expressions roll the same way. Their exact distributions are compared
+/// when both have one, and otherwise their forms with the operands of `+` and `*` sorted.
+fn equivalent(a: &Expr, b: &Expr) -> bool {
+    match (a.distribution(), b.distribution()) {
+        (Some(a), Some(b)) => {
+            // Leave out totals too unlikely to matter, and allow for rounding
+            let likely = |chances: BTreeMap<i32, f64>| -> Vec<(i32, f64)> {
+                chances
+                    .into_iter()
+                    .filter(|&(_, chance)| chance > 1e-12)
+                    .collect()
+            };
+            let (a, b) = (likely(a), likely(b));
+            a.len() == b.len()
+                && a.iter()
+                    .zip(&b)
+                    .all(|((x, p), (y, q))| x == y && (p - q).abs() < 1e-9)
+        }
+        _ => sorted(a).to_string() == sorted(b).to_string(),
+    }
+}
+
+/// Returns an expression with the operands of each chain of `+` or `*` in sorted order, so
+/// that reordered sums and products look the same.
+fn sorted(expr: &Expr) -> Expr {
+    match expr {
+        Expr::Binary(_, op @ (Op::Add | Op::Mul), _) => {
+            let mut operands = vec![];
+            collect_operands(expr, *op, &mut operands);
+            let mut operands: Vec<Expr> = operands.into_iter().map(sorted).collect();
+            operands.sort_by_key(Expr::to_string);
+            let mut operands = operands.into_iter();
+            let first = operands.next().unwrap();
+            operands.fold(first, |lhs, rhs| {
+                Expr::Binary(Box::new(lhs), *op, Box::new(rhs))
+            })
+        }
+        Expr::Binary(lhs, op, rhs) => {
+            Expr::Binary(Box::new(sorted(lhs)), *op, Box::new(sorted(rhs)))
+        }
+        Expr::Group(members, keep) => {
+            Expr::Group(members.iter().map(sorted).collect(), keep.clone())
+        }
+        Expr::Negate(inner) => Expr::Negate(Box::new(sorted(inner))),
+        _ => expr.clone(),
+    }
+}
+
+/// Collects the operands of a chain of the same operator, e.g. `a`, `b` and `c` of `a+b+c`.
+fn collect_operands<'a>(expr: &'a Expr, op: Op, operands: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::Binary(lhs, inner, rhs) if *inner == op => {
+            collect_operands(lhs, op, operands);
+            collect_operands(rhs, op, operands);
+        }
+        _ => operands.push(expr),
+    }
+}
+
+/// Computes the Levenshtein edit distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = prev[j] + if ca == cb { 0 } else { 1 };
+            curr[j + 1] = substitution.min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    expr: Expr,
+    tiers: Option<Tiers>,
+    map: Option<ResultMap>,
+    label: Option<String>,
+}
+
+impl Entry {
+    /// Returns the tier label and mapped text reached by a total.
+    fn labels(&self, total: i32) -> Vec<&str> {
+        let tier = self.tiers.as_ref().map(|tiers| tiers.label(total));
+        let text = self.map.as_ref().and_then(|map| map.get(total));
+        tier.into_iter().chain(text).collect()
+    }
+
+    /// Describes an outcome of this entry, including its expected value and any labels. With
+    /// `luck`, also shows how far the total deviated from the expected value, and with
+    /// `color`, the dice are colored.
+    fn describe(&self, outcome: &ExprOutcome, luck: bool, color: bool) -> String {
+        let mut line = if color {
+            format!("{:#}", outcome)
+        } else {
+            outcome.to_string()
+        };
+        if let Some(expected) = self.expr.expected_total() {
+            let delta = outcome.total() as f64 - expected;
+            // Fixed totals have no spread worth showing
+            let expected = match self.expr.standard_deviation().filter(|&sd| sd > 0.0) {
+                Some(deviation) => format!("{}, σ {:.2}", expected, deviation),
+                None => expected.to_string(),
+            };
+            if !luck {
+                line.push_str(&format!(" (Expected: {})", expected));
+            } else if delta > 0.0 {
+                line.push_str(&format!(
+                    " (Expected: {}, {:+.1} above expected)",
+                    expected, delta
+                ));
+            } else if delta < 0.0 {
+                line.push_str(&format!(
+                    " (Expected: {}, {:+.1} below expected)",
+                    expected, delta
+                ));
+            } else {
+                line.push_str(&format!(" (Expected: {}, exactly as expected)", expected));
+            }
+        }
+        for label in self.labels(outcome.total()) {
+            line.push_str(&format!(" -> {}", label));
+        }
+        line
+    }
+
+    /// Formats an outcome as a single porcelain record. The fields are, in order: the
+    /// expression, the total, the expected total (empty if unknown) and the reached tier
+    /// and mapped text joined by `;` (empty if none).
+    fn porcelain(&self, outcome: &ExprOutcome, separator: &str) -> String {
+        let expected = self
+            .expr
+            .expected_total()
+            .map(|expected| expected.to_string())
+            .unwrap_or_default();
+        let fields = [
+            self.expr.to_string(),
+            outcome.total().to_string(),
+            expected,
+            self.labels(outcome.total()).join(";"),
+        ];
+        fields.join(separator)
+    }
+}
+
+/// Describes the matched sets of a roll as width×height, e.g. `sets: 3×7, 2×2 (loose: 1, 5)`,
+/// for One Roll Engine games.
+fn describe_sets(outcome: &ExprOutcome) -> String {
+    let sets = outcome.sets();
+    let loose: Vec<_> = outcome
+        .tally()
+        .into_iter()
+        .filter(|&(_, count)| count == 1)
+        .map(|(value, _)| value.to_string())
+        .collect();
+    let mut description = if sets.is_empty() {
+        String::from("sets: none")
+    } else {
+        let sets: Vec<_> = sets
+            .iter()
+            .map(|(width, height)| format!("{}×{}", width, height))
+            .collect();
+        format!("sets: {}", sets.join(", "))
+    };
+    if !loose.is_empty() {
+        description.push_str(&format!(" (loose: {})", loose.join(", ")));
+    }
+    description
+}
+
+/// Runs a post-roll hook command through the shell, filling in the `{expr}`, `{total}`,
+/// `{tag}` and `{result}` fields of the roll. Failures are logged rather than reported, so
+/// a broken hook never hides the roll itself.
+fn run_hook(command: &str, entry: &Entry, outcome: &ExprOutcome) {
+    let command = command
+        .replace("{expr}", &entry.expr.to_string())
+        .replace("{total}", &outcome.total().to_string())
+        .replace("{tag}", entry.label.as_deref().unwrap_or(""))
+        .replace("{result}", &outcome.to_string());
+    match Command::new("sh").arg("-c").arg(&command).status() {
+        Ok(status) if !status.success() => warn!(%command, %status, "hook failed"),
+        Ok(_) => debug!(%command, "ran hook"),
+        Err(why) => warn!(%command, %why, "failed to run hook"),
+    }
+}
+
+/// Raises a desktop notification for a roll with `notify-send` or, on macOS, `osascript`.
+/// Like hooks, failures are logged rather than reported.
+fn notify(entry: &Entry, outcome: &ExprOutcome) {
+    let title = match &entry.label {
+        Some(label) => format!("{} [{}]", entry.expr, label),
+        None => entry.expr.to_string(),
+    };
+    let body = outcome.to_string();
+    let sent = Command::new("notify-send")
+        .arg(&title)
+        .arg(&body)
+        .status()
+        .or_else(|_| {
+            // Debug formatting quotes and escapes the strings as AppleScript expects
+            let script = format!("display notification {:?} with title {:?}", body, title);
+            Command::new("osascript").arg("-e").arg(script).status()
+        });
+    match sent {
+        Ok(status) if !status.success() => warn!(%title, %status, "notification failed"),
+        Ok(_) => debug!(%title, "sent notification"),
+        Err(why) => warn!(%title, %why, "failed to send notification"),
+    }
+}
+
+/// Places text on the system clipboard using the first clipboard command available: `pbcopy`
+/// on macOS, `wl-copy`, `xclip` or `xsel` on Linux, and `clip` on Windows.
+fn copy_to_clipboard(text: &str) -> Result<(), &'static str> {
+    let commands: [&[&str]; 5] = [
+        &["pbcopy"],
+        &["wl-copy"],
+        &["xclip", "-selection", "clipboard"],
+        &["xsel", "--clipboard", "--input"],
+        &["clip"],
+    ];
+    for command in commands.iter() {
+        let mut child = match Command::new(command[0])
+            .args(&command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(_) => continue,
+        };
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or("Failed to copy to the clipboard.")?;
+        stdin
+            .write_all(text.as_bytes())
+            .map_err(|_| "Failed to copy to the clipboard.")?;
+        drop(stdin);
+        let status = child
+            .wait()
+            .map_err(|_| "Failed to copy to the clipboard.")?;
+        if !status.success() {
+            return Err("Failed to copy to the clipboard.");
+        }
+        debug!(command = command[0], "copied to clipboard");
+        return Ok(());
+    }
+    Err("No clipboard command found; install wl-copy, xclip or xsel.")
+}
+
+/// Returns the user's config directory: `$XDG_CONFIG_HOME`, or `~/.config` by default.
+fn config_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")),
+    }
 }
 
 struct Context {
-    macros: HashMap<String, Vec<Roll>>,
+    macros: HashMap<String, Vec<Entry>>,
+    constants: HashMap<String, i32>,
+    porcelain: Option<String>,
+    luck: bool,
+    defenses: Option<Defenses>,
+    on_roll: Option<String>,
+    take: Option<u32>,
+    max_explosions: usize,
+    advantages: Vec<String>,
+    quiet: bool,
+    portent: Option<u32>,
+    notify: bool,
+    tally: bool,
+    sets: bool,
+    rng: StdRng,
 }
 
 impl Context {
-    fn new() -> Context {
+    fn new(rng: StdRng) -> Context {
         Context {
             macros: HashMap::new(),
+            constants: HashMap::new(),
+            porcelain: None,
+            luck: false,
+            defenses: None,
+            on_roll: None,
+            take: None,
+            max_explosions: MAX_EXPLOSIONS,
+            advantages: vec![],
+            quiet: false,
+            portent: None,
+            notify: false,
+            tally: false,
+            sets: false,
+            rng,
+        }
+    }
+
+    /// Binds a named constant from a `name=value` pair.
+    fn set_constant(&mut self, binding: &str) -> Result<(), &'static str> {
+        let mut iter = binding.splitn(2, '=');
+        let name = iter.next().unwrap_or("");
+        let value = iter
+            .next()
+            .ok_or("Constants must be of the form name=value.")?;
+        if name.is_empty() {
+            return Err("Constants must be of the form name=value.");
+        }
+        let value = value
+            .parse::<i32>()
+            .map_err(|_| "Failed to parse constant value.")?;
+        self.constants.insert(name.to_lowercase(), value);
+        Ok(())
+    }
+
+    /// Loads a character sheet of `name = value` lines, binding each numeric value as a named
+    /// constant. An `advantage = [skills]` line lists the skills rolled with advantage, and
+    /// other text values, blank lines, `[section]` headers and `#` comments are ignored.
+    fn load_sheet(&mut self, path: &str) -> Result<(), &'static str> {
+        let contents = fs::read_to_string(path).map_err(|_| "Failed to read sheet file.")?;
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with('[') {
+                continue;
+            }
+            let (name, value) = line
+                .split_once('=')
+                .ok_or("Sheet lines must be of the form name = value.")?;
+            let (name, value) = (name.trim().to_lowercase(), value.trim());
+            if name == "advantage" {
+                let skills = value.trim_start_matches('[').trim_end_matches(']');
+                self.advantages.extend(
+                    skills
+                        .split(',')
+                        .map(|skill| skill.trim().trim_matches('"').to_lowercase())
+                        .filter(|skill| !skill.is_empty()),
+                );
+            } else if let Ok(value) = value.parse::<i32>() {
+                self.constants.insert(name, value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces named constants such as `+prof` with their bound values.
+    fn substitute_constants(&self, arg: &str) -> Result<String, RollError> {
+        let mut output = String::with_capacity(arg.len());
+        let mut last = 0;
+        for cap in CONSTANT_REGEX.captures_iter(arg) {
+            let whole = cap.get(0).unwrap();
+            let value = match self.constants.get(&cap["name"]) {
+                Some(value) => value,
+                // Dice without a count, as in `3+d4`, are not constants
+                None if DIE_NAME_REGEX.is_match(&cap["name"]) => continue,
+                None => {
+                    return Err(RollError::Parse {
+                        input: arg.to_string(),
+                        position: cap.name("name").unwrap().start(),
+                        reason: "Unknown constant.",
+                    })
+                }
+            };
+            let value = if &cap["sign"] == "-" { -value } else { *value };
+            output.push_str(&arg[last..whole.start()]);
+            output.push_str(&format!("{:+}", value));
+            last = whole.end();
         }
+        output.push_str(&arg[last..]);
+        Ok(output)
     }
 
-    fn load_macros(&mut self) {
-        let macro_file = include_str!("../macros.txt");
+    /// Loads the built-in macros, then the user's macros from `path` or, by default, from
+    /// `roll/macros.txt` in the config directory. User macros replace built-ins of the same
+    /// name.
+    fn load_macros(&mut self, path: Option<&str>) -> Result<(), String> {
+        let _span = debug_span!("load_macros").entered();
+        self.add_macros(include_str!("../macros.txt"))
+            .expect("Parsing error.");
 
-        for line in macro_file.lines() {
+        let (path, explicit) = match path {
+            Some(path) => (PathBuf::from(path), true),
+            None => match config_dir() {
+                Some(dir) => (dir.join("roll").join("macros.txt"), false),
+                None => return Ok(()),
+            },
+        };
+        // A missing default file just means the user has no macros of their own
+        if !explicit && !path.exists() {
+            return Ok(());
+        }
+        let contents = fs::read_to_string(&path)
+            .map_err(|_| format!("Failed to read macro file {}.", path.display()))?;
+        self.add_macros(&contents)
+            .map_err(|why| format!("In {}: {}", path.display(), why))
+    }
+
+    /// Adds the macros defined by each line of a macro file, skipping blank lines and `#`
+    /// comments.
+    fn add_macros(&mut self, contents: &str) -> Result<(), String> {
+        for line in contents.lines() {
             let mut iter = line.split_whitespace();
-            let name = iter.next().unwrap();
+            let name = match iter.next() {
+                Some(name) if !name.starts_with('#') => name,
+                _ => continue,
+            };
             let rolls = iter.map(|roll| roll.to_string());
-            let rolls = self.parse_rolls(rolls).expect("Parsing error.");
+            let rolls = self.parse_rolls(rolls)?;
             self.macros.insert(name.to_string(), rolls);
         }
+        Ok(())
     }
 
-    fn parse_rolls(&self, args: impl Iterator<Item = String>) -> Result<Vec<Roll>, &'static str> {
-        let mut rolls: Vec<Roll> = vec![];
-        for arg in args {
+    fn parse_rolls(&self, args: impl Iterator<Item = String>) -> Result<Vec<Entry>, String> {
+        let _span = debug_span!("parse").entered();
+        let mut rolls: Vec<Entry> = vec![];
+        let mut errors = vec![];
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            // Keep going after a bad argument so every error can be reported at once
+            if let Err(why) = self.parse_arg(&arg, &mut args, &mut rolls) {
+                errors.push(format!("'{}': {}", arg, why));
+            }
+        }
+
+        match errors.len() {
+            0 => Ok(rolls),
+            1 => Err(errors.remove(0)),
+            n => Err(format!(
+                "{} invalid arguments:\n  {}",
+                n,
+                errors.join("\n  ")
+            )),
+        }
+    }
+
+    /// Parses an expression after substituting any named constants.
+    fn parse_expr(&self, arg: &str) -> Result<Expr, RollError> {
+        let substituted = self.substitute_constants(arg)?;
+        let expr = substituted.parse()?;
+        debug!(input = %arg, %substituted, ?expr, "parsed");
+        Ok(expr)
+    }
+
+    /// Suggests the macros closest to a misspelled argument, if any are close enough.
+    fn suggest_macros(&self, arg: &str) -> Vec<&str> {
+        let max_distance = (arg.chars().count() / 3).clamp(1, 3);
+        let mut candidates: Vec<_> = self
+            .macros
+            .keys()
+            .map(|name| (edit_distance(arg, name), name.as_str()))
+            .filter(|&(distance, _)| distance <= max_distance)
+            .collect();
+        candidates.sort();
+        let best = candidates.first().map(|&(distance, _)| distance);
+        candidates
+            .into_iter()
+            .filter(|&(distance, _)| Some(distance) == best)
+            .map(|(_, name)| name)
+            .collect()
+    }
+
+    /// Parses a single argument, consuming any following arguments that belong to it.
+    fn parse_arg<I: Iterator<Item = String>>(
+        &self,
+        arg: &str,
+        args: &mut Peekable<I>,
+        rolls: &mut Vec<Entry>,
+    ) -> Result<(), String> {
+        if arg.starts_with('{') && arg.contains(':') {
+            // Gather the tokens of a result map until its braces are balanced
+            let mut map = arg.to_string();
+            while map.matches('{').count() > map.matches('}').count() {
+                let next = args.next().ok_or("Unbalanced braces in result map.")?;
+                map.push(' ');
+                map.push_str(&next);
+            }
+            let entry = rolls.last_mut().ok_or("Result maps must follow a roll.")?;
+            entry.map = Some(ResultMap::parse(&map)?);
+        } else if let Some((expr, label)) = arg.split_once('#') {
+            // Label a roll, as in `1d20+7#attack` or `1d20+7 # sneak attack`. Like a comment,
+            // the label runs to the end of the input.
+            if !expr.is_empty() {
+                self.parse_arg(expr, args, rolls)?;
+            }
+            let words: Vec<String> = iter::once(label.to_string())
+                .chain(args)
+                .filter(|word| !word.is_empty())
+                .collect();
+            if words.is_empty() {
+                return Err("Missing label after #.".to_string());
+            }
+            let entry = rolls.last_mut().ok_or("Labels must follow a roll.")?;
+            entry.label = Some(words.join(" "));
+        } else if arg == "tiers" {
+            // Attach tiers to the preceding roll
+            let thresholds = args.next().ok_or("Missing thresholds for tiers.")?;
+            let labels = if args.peek().map(String::as_str) == Some("labels") {
+                args.next();
+                Some(args.next().ok_or("Missing tier labels.")?)
+            } else {
+                None
+            };
+            let entry = rolls.last_mut().ok_or("Tiers must follow a roll.")?;
+            entry.tiers = Some(Tiers::parse(&thresholds, labels.as_deref())?);
+        } else if let Some(cap) = REPEAT_REGEX.captures(arg) {
+            // Roll the expression several times, e.g. `6x4d6h3`
+            let count = cap["count"]
+                .parse::<usize>()
+                .map_err(|_| "Failed to parse repeat count.")?;
+            if count == 0 || count > MAX_REPEAT {
+                return Err(format!(
+                    "Repeat counts must be between 1 and {}.",
+                    MAX_REPEAT
+                ));
+            }
+            let start = rolls.len();
+            self.parse_arg(&cap["expr"], args, rolls)?;
+            let repeated = rolls[start..].to_vec();
+            for _ in 1..count {
+                rolls.extend(repeated.iter().cloned());
+            }
+        } else if let Some(sub_rolls) = self.macros.get(arg) {
             // Look it up in macros
-            if let Some(sub_rolls) = self.macros.get(&arg) {
-                for roll in sub_rolls {
-                    rolls.push(roll.clone());
+            let _span = debug_span!("expand_macro", name = %arg).entered();
+            for roll in sub_rolls {
+                debug!(expr = %roll.expr, "expanded");
+                rolls.push(roll.clone());
+            }
+        } else {
+            // Try to parse it. Brackets right after dice give a damage type, as in
+            // `2d6[fire]+1d4[cold]`, while brackets ending the expression anywhere else give a
+            // label, as in `1d20+7[attack]`.
+            let (parsed, label) = match (self.parse_expr(arg), TAG_REGEX.captures(arg)) {
+                (Err(_), Some(cap)) => (
+                    self.parse_expr(cap.name("expr").unwrap().as_str()),
+                    Some(cap["tag"].to_string()),
+                ),
+                (parsed, _) => (parsed, None),
+            };
+            let expr = parsed.map_err(|why| {
+                let suggestions = self.suggest_macros(arg);
+                if suggestions.is_empty() {
+                    point_at(&why)
+                } else {
+                    format!(
+                        "Not a valid roll or known macro; did you mean '{}'?",
+                        suggestions.join("' or '")
+                    )
                 }
+            })?;
+            rolls.push(Entry {
+                expr,
+                tiers: None,
+                map: None,
+                label,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Rolls each entry and writes the results to `out`, with colored dice only if `color`.
+    fn process_rolls(
+        &mut self,
+        rolls: Vec<Entry>,
+        out: &mut impl Write,
+        color: bool,
+    ) -> io::Result<()> {
+        let _span = debug_span!("evaluate").entered();
+        let mut total: i32 = 0;
+        let mut damage: Vec<(String, i32)> = vec![];
+        for entry in rolls.iter() {
+            // A portent replaces the first d20 roll and is then used up
+            let portent = self.portent.filter(|_| entry.expr.has_d20());
+            let max = self.max_explosions;
+            let outcome = match (portent, self.take) {
+                (Some(value), _) => {
+                    self.portent = None;
+                    entry.expr.take_first_limited(value, &mut self.rng, max)
+                }
+                (None, Some(value)) => entry.expr.take_limited(value, &mut self.rng, max),
+                (None, None) => entry.expr.roll_limited(&mut self.rng, max),
+            };
+            debug!(expr = %entry.expr, ?outcome, "rolled");
+            total = total.saturating_add(outcome.total());
+            match &self.porcelain {
+                // Quiet output is just the total, or the faces of symbol dice
+                _ if self.quiet && entry.expr.is_numeric() => writeln!(out, "{}", outcome.total())?,
+                _ if self.quiet => writeln!(out, "{}", outcome)?,
+                Some(separator) => writeln!(out, "{}", entry.porcelain(&outcome, separator))?,
+                None => {
+                    let mut line = entry.describe(&outcome, self.luck, color);
+                    if let Some(value) = portent {
+                        line.push_str(&format!(" (portent: {})", value));
+                    }
+                    match &entry.label {
+                        Some(label) => writeln!(out, "{} [{}]: {}", entry.expr, label, line)?,
+                        None => writeln!(out, "{}: {}", entry.expr, line)?,
+                    }
+                    // A tally of faces makes pools easier to read at a glance
+                    let tally = if self.tally { outcome.tally() } else { vec![] };
+                    if !tally.is_empty() {
+                        let tally: Vec<_> = tally
+                            .iter()
+                            .map(|(value, count)| format!("{}×{}", value, count))
+                            .collect();
+                        writeln!(out, "  {}", tally.join(", "))?;
+                    }
+                    if self.sets {
+                        writeln!(out, "  {}", describe_sets(&outcome))?;
+                    }
+                }
+            }
+
+            if let Some(command) = &self.on_roll {
+                run_hook(command, entry, &outcome);
+            }
+            if self.notify {
+                notify(entry, &outcome);
+            }
+
+            // Sum damage per type so defenses apply once to each type
+            for (kind, amount) in outcome.damage_by_type() {
+                let kind = kind.unwrap_or("untyped").to_string();
+                match damage.iter_mut().find(|(k, _)| *k == kind) {
+                    Some((_, total)) => *total = total.saturating_add(amount),
+                    None => damage.push((kind, amount)),
+                }
+            }
+        }
+        if let Some(defenses) = &self.defenses {
+            let mut applied: i32 = 0;
+            for (kind, amount) in damage {
+                let (dealt, defense) = defenses.apply(&kind, amount);
+                applied = applied.saturating_add(dealt);
+                match defense {
+                    _ if self.quiet => {}
+                    Some(defense) => {
+                        writeln!(out, "{}: {} -> {} ({})", kind, amount, dealt, defense)?
+                    }
+                    None => writeln!(out, "{}: {}", kind, amount)?,
+                }
+            }
+            return if self.quiet {
+                writeln!(out, "{}", applied)
             } else {
-                // Try to parse it
-                let roll = arg.parse()?;
-                rolls.push(roll);
+                writeln!(out, "Applied damage: {}", applied)
+            };
+        }
+        let numeric = rolls.iter().filter(|entry| entry.expr.is_numeric()).count();
+        if numeric > 1 && self.porcelain.is_none() && !self.quiet {
+            writeln!(out, "Total: {}", total)?;
+        }
+        Ok(())
+    }
+
+    /// Reads roll expressions from a prompt until `quit` or the end of input, keeping the
+    /// loaded macros between rolls.
+    fn repl(&mut self) -> Result<(), &'static str> {
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        loop {
+            print!("> ");
+            io::stdout()
+                .flush()
+                .map_err(|_| "Failed to write prompt.")?;
+            let line = match lines.next() {
+                Some(line) => line.map_err(|_| "Failed to read input.")?,
+                None => {
+                    println!();
+                    return Ok(());
+                }
+            };
+            match line.trim() {
+                "" => {}
+                "quit" | "exit" => return Ok(()),
+                "help" => {
+                    println!("Enter dice such as 2d6+3 or 4d6h3, or a macro such as stats.");
+                    println!("Several rolls can be entered on one line. Type quit to exit.");
+                }
+                line => match self.parse_rolls(normalize(vec![line.to_string()]).into_iter()) {
+                    Ok(rolls) => self
+                        .process_rolls(rolls, &mut io::stdout().lock(), stdout_color())
+                        .map_err(|_| "Failed to write output.")?,
+                    Err(why) => println!("Error: {}", why),
+                },
+            }
+        }
+    }
+
+    /// Rolls the expressions on each line of standard input, as when input is piped in or
+    /// the only argument is `-`. Bad lines are reported without stopping the batch.
+    fn batch(&mut self) -> Result<(), &'static str> {
+        let stdin = io::stdin();
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        for line in stdin.lock().lines() {
+            let line = line.map_err(|_| "Failed to read input.")?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match self.parse_rolls(normalize(vec![line]).into_iter()) {
+                Ok(rolls) => self.process_rolls(rolls, &mut out, stdout_color()),
+                Err(why) => writeln!(out, "Error: {}", why),
+            }
+            .map_err(|_| "Failed to write output.")?;
+        }
+        Ok(())
+    }
+
+    /// Watches a directory for `.roll` request files, each holding the arguments of one
+    /// invocation, and answers each with a `.result` file of the same name before removing
+    /// the request. Other tools should write requests under another name and then rename
+    /// them, so that half-written files are never read.
+    fn watch(&mut self, mut args: impl Iterator<Item = String>) -> Result<(), String> {
+        let dir = args.next().ok_or("Missing directory for watch.")?;
+        let mut interval = 500;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--interval" => {
+                    let value = args.next().ok_or("Missing value for --interval.")?;
+                    interval = value
+                        .parse::<u64>()
+                        .map_err(|_| "Failed to parse interval.")?;
+                }
+                _ => return Err("Unknown argument to watch.".to_string()),
+            }
+        }
+
+        loop {
+            let entries = fs::read_dir(&dir).map_err(|_| "Failed to read watch directory.")?;
+            let mut requests: Vec<_> = entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "roll"))
+                .collect();
+            requests.sort();
+
+            // A request that cannot be answered is reported without stopping the watch
+            for request in requests {
+                if let Err(why) = self.answer(&request) {
+                    println!("Error: {}: {}", request.display(), why);
+                }
+            }
+
+            thread::sleep(Duration::from_millis(interval));
+        }
+    }
+
+    /// Answers a single `watch` request file with a plain `.result` file and removes it.
+    /// Requests that cannot be read or rolled are answered with their error.
+    fn answer(&mut self, request: &Path) -> Result<(), String> {
+        let _span = debug_span!("request", path = %request.display()).entered();
+        let mut output = vec![];
+        match fs::read_to_string(request) {
+            Ok(contents) => match self.parse_rolls(normalize(vec![contents]).into_iter()) {
+                // Result files are read by other programs, so they are never colored
+                Ok(rolls) => self.process_rolls(rolls, &mut output, false),
+                Err(why) => writeln!(output, "Error: {}", why),
+            },
+            Err(_) => writeln!(output, "Error: Failed to read request file."),
+        }
+        .map_err(|_| "Failed to write result.")?;
+        fs::write(request.with_extension("result"), output)
+            .map_err(|_| "Failed to write result file.")?;
+        fs::remove_file(request).map_err(|_| "Failed to remove request file.")?;
+        debug!("answered");
+        Ok(())
+    }
+
+    /// Rolls every `[[ ]]` span in a piece of prose, such as `hits for [[2d8+4]] damage`, and
+    /// prints the text with each span replaced by its total.
+    fn inline(&mut self, text: &str) -> Result<(), String> {
+        let mut output = String::with_capacity(text.len());
+        let mut last = 0;
+        for cap in INLINE_REGEX.captures_iter(text) {
+            let whole = cap.get(0).unwrap();
+            let rolls = self.parse_rolls(normalize(vec![cap["expr"].to_string()]).into_iter())?;
+            let outcomes: Vec<_> = rolls
+                .iter()
+                .map(|entry| entry.expr.roll_limited(&mut self.rng, self.max_explosions))
+                .collect();
+            let result = match outcomes.as_slice() {
+                [outcome @ ExprOutcome::Symbols(_)] => outcome.to_string(),
+                _ => outcomes
+                    .iter()
+                    .map(ExprOutcome::total)
+                    .sum::<i32>()
+                    .to_string(),
+            };
+            output.push_str(&text[last..whole.start()]);
+            output.push_str(&result);
+            last = whole.end();
+        }
+        output.push_str(&text[last..]);
+        println!("{}", output);
+        Ok(())
+    }
+
+    /// Rolls once on each named macro table, printing each result after its label.
+    fn roll_tables(&mut self, tables: &[(&str, &str)]) -> Result<(), &'static str> {
+        for (label, name) in tables {
+            let entries = self.macros.get(*name).ok_or("Unknown table.")?;
+            for entry in entries {
+                let outcome = entry.expr.roll_limited(&mut self.rng, self.max_explosions);
+                println!(
+                    "{}: {}",
+                    label,
+                    entry.describe(&outcome, self.luck, stdout_color())
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses loosely written input as a single expression in canonical form.
+    fn parse_canonical(&self, input: &str) -> Result<Expr, String> {
+        match normalize(vec![input.to_string()]).as_slice() {
+            [token] => Ok(self
+                .parse_expr(token)
+                .map_err(|why| format!("'{}': {}", input, point_at(&why)))?
+                .canonical()),
+            _ => Err(format!("'{}': Expected a single expression.", input)),
+        }
+    }
+
+    /// Prints the canonical form of an expression, e.g. `normalize "2d20kh1 + 3 + 2"`.
+    fn print_canonical(&self, args: &[String]) -> Result<(), String> {
+        println!("{}", self.parse_canonical(&args.join(" "))?);
+        Ok(())
+    }
+
+    /// Reports whether two expressions, e.g. `equiv 1d6+1d6 2d6`, roll the same way.
+    fn equivalent(&self, args: &[String]) -> Result<(), String> {
+        let (a, b) = match args {
+            [a, b] => (self.parse_canonical(a)?, self.parse_canonical(b)?),
+            _ => return Err("equiv takes exactly two expressions.".to_string()),
+        };
+        if a.to_string() == b.to_string() {
+            println!("Equivalent: both are {}", a);
+        } else if equivalent(&a, &b) {
+            println!("Equivalent: {} and {}", a, b);
+        } else {
+            println!("Not equivalent: {} vs {}", a, b);
+        }
+        Ok(())
+    }
+
+    /// Reports suspicious constructs in each roll, e.g. `lint 4d6h5 1d4+20`. Rolls that fail
+    /// to parse, such as an exploding `1d1!`, are reported as errors.
+    fn lint(&self, args: Vec<String>) {
+        for arg in normalize(args) {
+            let entries = match self.parse_rolls(iter::once(arg)) {
+                Ok(entries) => entries,
+                Err(why) => {
+                    println!("error: {}", why);
+                    continue;
+                }
+            };
+            for entry in entries {
+                let warnings = entry.expr.lint();
+                if warnings.is_empty() {
+                    println!("{}: no problems found", entry.expr);
+                }
+                for warning in warnings {
+                    println!("{}: warning: {}", entry.expr, warning);
+                }
+            }
+        }
+    }
+
+    /// Computes a passive score such as `passive perception` as 10 plus the skill's bound
+    /// constant, adding 5 with advantage and subtracting 5 with disadvantage. Advantage comes
+    /// from `--adv` or the sheet's advantage list.
+    fn passive(&self, mut args: impl Iterator<Item = String>) -> Result<(), &'static str> {
+        let skill = args
+            .next()
+            .ok_or("Missing skill for passive.")?
+            .to_lowercase();
+        let mut adv = self.advantages.contains(&skill);
+        let mut dis = false;
+        for arg in args {
+            match arg.as_str() {
+                "--adv" => adv = true,
+                "--dis" => dis = true,
+                _ => return Err("Unknown argument to passive."),
             }
         }
+        let modifier = *self
+            .constants
+            .get(&skill)
+            .ok_or("Unknown skill; bind it with --set or --sheet.")?;
 
-        Ok(rolls)
+        // Advantage and disadvantage cancel each other out
+        let (bonus, note) = match (adv, dis) {
+            (true, false) => (5, " + 5 (advantage)"),
+            (false, true) => (-5, " - 5 (disadvantage)"),
+            _ => (0, ""),
+        };
+        let sign = if modifier < 0 { '-' } else { '+' };
+        println!(
+            "Passive {}: 10 {} {}{} = {}",
+            skill,
+            sign,
+            modifier.abs(),
+            note,
+            10 + modifier + bonus
+        );
+        Ok(())
     }
 
-    fn process_rolls(&self, rolls: Vec<Roll>) {
-        let mut rng = thread_rng();
-        let mut total = 0;
-        for roll in rolls.iter() {
-            let outcome = roll.roll(&mut rng);
-            total += outcome.total();
-            println!(
-                "{}: {} (Expected: {})",
-                roll,
-                outcome,
-                roll.expected_total()
+    /// Rolls on the hit location table for a creature shape, followed by a severity roll.
+    fn hit_location(&mut self, shape: &str) -> Result<(), &'static str> {
+        let location = format!("hitloc-{}", shape);
+        if !self.macros.contains_key(&location) {
+            return Err("Unknown hit location table.");
+        }
+        self.roll_tables(&[("Location", &location), ("Severity", "hitloc-severity")])
+    }
+
+    /// Generates an NPC statblock from `--cr`, `--type` and optionally `--standard`, then
+    /// rolls on the NPC trait tables.
+    fn npc(&mut self, mut args: impl Iterator<Item = String>) -> Result<(), &'static str> {
+        let mut cr = String::from("1");
+        let mut kind = String::from("humanoid");
+        let mut standard = false;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--cr" => cr = args.next().ok_or("Missing value for --cr.")?,
+                "--type" => {
+                    kind = args
+                        .next()
+                        .ok_or("Missing value for --type.")?
+                        .to_lowercase()
+                }
+                "--standard" => standard = true,
+                _ => return Err("Unknown argument to npc."),
+            }
+        }
+
+        let npc = Npc::generate(&kind, &cr, standard, &mut self.rng)?;
+        println!("{}", npc);
+        self.roll_tables(&[("Trait", "npc-trait"), ("Quirk", "npc-quirk")])
+    }
+
+    /// Runs a skill challenge that ends after `--successes` successes or `--failures` failures.
+    /// Each line of input is a check result: `s` or `f`, or a total compared against `--dc`.
+    /// With `--complications`, each failure also rolls on the `challenge-complication` table.
+    fn challenge(&mut self, mut args: impl Iterator<Item = String>) -> Result<(), &'static str> {
+        let mut needed = 3;
+        let mut allowed = 3;
+        let mut dc = None;
+        let mut complications = false;
+        while let Some(arg) = args.next() {
+            let mut number = |name| -> Result<i32, &'static str> {
+                args.next()
+                    .ok_or(name)?
+                    .parse()
+                    .map_err(|_| "Failed to parse challenge value.")
+            };
+            match arg.as_str() {
+                "--successes" => needed = number("Missing value for --successes.")?,
+                "--failures" => allowed = number("Missing value for --failures.")?,
+                "--dc" => dc = Some(number("Missing value for --dc.")?),
+                "--complications" => complications = true,
+                _ => return Err("Unknown argument to challenge."),
+            }
+        }
+
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        let (mut successes, mut failures) = (0, 0);
+        while successes < needed && failures < allowed {
+            print!(
+                "Check [{}/{} successes, {}/{} failures]: ",
+                successes, needed, failures, allowed
             );
+            io::stdout()
+                .flush()
+                .map_err(|_| "Failed to write prompt.")?;
+            let line = lines
+                .next()
+                .ok_or("Challenge cancelled.")?
+                .map_err(|_| "Failed to read input.")?;
+            let success = match (line.trim(), dc) {
+                ("s" | "success", _) => true,
+                ("f" | "failure", _) => false,
+                (total, Some(dc)) => match total.parse::<i32>() {
+                    Ok(total) => total >= dc,
+                    Err(_) => {
+                        println!("Enter s, f or a check total.");
+                        continue;
+                    }
+                },
+                _ => {
+                    println!("Enter s or f, or set --dc to enter check totals.");
+                    continue;
+                }
+            };
+            if success {
+                successes += 1;
+            } else {
+                failures += 1;
+                if complications {
+                    self.roll_tables(&[("Complication", "challenge-complication")])?;
+                }
+            }
+        }
+
+        if successes >= needed {
+            println!("Challenge succeeded with {} failures.", failures);
+        } else {
+            println!("Challenge failed with {} successes.", successes);
+        }
+        Ok(())
+    }
+
+    /// Resolves a downtime activity such as `downtime carouse --bonus 2` by rolling its
+    /// `downtime-<activity>` macro and looking up the outcome with the bonus added.
+    fn downtime(&mut self, mut args: impl Iterator<Item = String>) -> Result<(), &'static str> {
+        let activity = args.next().ok_or("Missing downtime activity.")?;
+        let mut bonus = 0;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--bonus" => {
+                    let value = args.next().ok_or("Missing value for --bonus.")?;
+                    bonus = value.parse::<i32>().map_err(|_| "Failed to parse bonus.")?;
+                }
+                _ => return Err("Unknown argument to downtime."),
+            }
+        }
+        let entries = self
+            .macros
+            .get(&format!("downtime-{}", activity.to_lowercase()))
+            .ok_or("Unknown downtime activity.")?;
+
+        for entry in entries {
+            let outcome = entry.expr.roll_limited(&mut self.rng, self.max_explosions);
+            let total = outcome.total() + bonus;
+            let mut line = if bonus == 0 {
+                outcome.to_string()
+            } else {
+                format!("{} {:+} = {}", outcome, bonus, total)
+            };
+            for label in entry.labels(total) {
+                line.push_str(&format!(" -> {}", label));
+            }
+            println!("{}", line);
+        }
+
+        Ok(())
+    }
+
+    /// Runs the overland travel procedure for `--days` days through a `--terrain`: each day
+    /// rolls the weather, a navigation check with an optional `--nav` bonus and an encounter
+    /// check, rolling on the terrain's encounter table when an encounter occurs.
+    fn travel(&mut self, mut args: impl Iterator<Item = String>) -> Result<(), &'static str> {
+        let mut days = 1;
+        let mut terrain = String::from("plains");
+        let mut nav = 0;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--days" => {
+                    let value = args.next().ok_or("Missing value for --days.")?;
+                    days = value.parse::<u32>().map_err(|_| "Failed to parse days.")?;
+                }
+                "--terrain" => {
+                    terrain = args
+                        .next()
+                        .ok_or("Missing value for --terrain.")?
+                        .to_lowercase()
+                }
+                "--nav" => {
+                    let value = args.next().ok_or("Missing value for --nav.")?;
+                    nav = value
+                        .parse::<i32>()
+                        .map_err(|_| "Failed to parse navigation bonus.")?;
+                }
+                _ => return Err("Unknown argument to travel."),
+            }
+        }
+
+        // Navigation DC and the highest d6 roll that triggers an encounter
+        let (dc, encounter_on) = match terrain.as_str() {
+            "plains" | "road" => (10, 1),
+            "hills" | "desert" => (12, 1),
+            "forest" | "swamp" | "mountains" => (15, 2),
+            _ => return Err("Unknown terrain."),
+        };
+        let encounters = format!("travel-encounter-{}", terrain);
+        let encounters = if self.macros.contains_key(&encounters) {
+            encounters.as_str()
+        } else {
+            "travel-encounter"
+        };
+
+        let navigation = Roll::new(1, 20, None, None, Some(nav));
+        let encounter_check = Roll::new(1, 6, None, None, None);
+        for day in 1..=days {
+            println!("Day {}", day);
+            self.roll_tables(&[("Weather", "travel-weather")])?;
+
+            let outcome = navigation.roll(&mut self.rng);
+            let result = if outcome.total() >= dc {
+                "on course"
+            } else {
+                "lost"
+            };
+            println!("Navigation: {} vs DC {} -> {}", outcome, dc, result);
+
+            let outcome = encounter_check.roll(&mut self.rng);
+            if outcome.total() <= encounter_on {
+                println!("Encounter check: {} -> encounter", outcome);
+                self.roll_tables(&[("Encounter", encounters)])?;
+            } else {
+                println!("Encounter check: {} -> none", outcome);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The seed every `selftest` case is rolled with.
+const SELFTEST_SEED: u64 = 0;
+
+/// Expressions run by `selftest`, covering each kind of roll the parser and formatters handle.
+const SELFTEST_EXPRS: &[&str] = &[
+    "1d20+5",
+    "4d6h3+2",
+    "2d20l1",
+    "8d6",
+    "3d6r1",
+    "4df",
+    "1d6!",
+    "d%",
+    "4d6dl1",
+    "10d10>=8",
+    "3d6[+1 each]",
+    "{1d20+5,1d20+3}h1",
+    "(2d6+3)*2",
+    "3d[skull,shield,blank]",
+];
+
+/// Rolls every `selftest` case under the fixed seed, returning each case's header and output.
+fn selftest_cases() -> Result<Vec<(String, String)>, String> {
+    let mut cases = vec![];
+    for mode in &["plain", "color", "porcelain", "quiet"] {
+        for expr in SELFTEST_EXPRS {
+            let mut context = Context::new(StdRng::seed_from_u64(SELFTEST_SEED));
+            match *mode {
+                "porcelain" => context.porcelain = Some(String::from("\t")),
+                "quiet" => context.quiet = true,
+                _ => {}
+            }
+            let rolls = context.parse_rolls(iter::once(expr.to_string()))?;
+            let mut output = vec![];
+            context
+                .process_rolls(rolls, &mut output, *mode == "color")
+                .map_err(|_| "Failed to write output.")?;
+            let output = String::from_utf8(output).map_err(|_| "Output is not valid UTF-8.")?;
+            cases.push((format!("> {} {}", mode, expr), output));
+        }
+    }
+    Ok(cases)
+}
+
+/// Checks that this build rolls and formats a battery of expressions exactly as the golden
+/// outputs in `selftest.txt` expect. `selftest --print` prints the outputs of this build
+/// instead, in the same format.
+fn selftest(args: &[String]) -> Result<(), String> {
+    let cases = selftest_cases()?;
+    match args {
+        [] => {}
+        [flag] if flag == "--print" => {
+            for (header, output) in cases {
+                print!("{}\n{}", header, output);
+            }
+            return Ok(());
+        }
+        _ => return Err("Unknown argument to selftest.".to_string()),
+    }
+
+    // Split the golden file into cases at each header line
+    let mut golden: HashMap<&str, String> = HashMap::new();
+    let mut current = None;
+    for line in include_str!("../selftest.txt").lines() {
+        if line.starts_with("> ") {
+            current = Some(line);
+            golden.insert(line, String::new());
+        } else if let Some(header) = current {
+            let output = golden.get_mut(header).unwrap();
+            output.push_str(line);
+            output.push('\n');
         }
-        if rolls.len() > 1 {
-            println!("Total: {}", total);
+    }
+
+    let mut failures = 0;
+    for (header, output) in &cases {
+        match golden.get(header.as_str()) {
+            Some(expected) if expected == output => {}
+            Some(expected) => {
+                failures += 1;
+                println!("FAILED {}", &header[2..]);
+                println!("  expected: {:?}", expected);
+                println!("  actual:   {:?}", output);
+            }
+            None => {
+                failures += 1;
+                println!("FAILED {}: no golden output", &header[2..]);
+            }
         }
     }
+    if failures > 0 {
+        return Err(format!(
+            "{} of {} selftest cases failed.",
+            failures,
+            cases.len()
+        ));
+    }
+    println!("All {} selftest cases passed.", cases.len());
+    Ok(())
+}
+
+fn run() -> Result<(), String> {
+    let mut context = Context::new(StdRng::from_entropy());
+    let mut avrae = false;
+    let mut debug = false;
+    let mut porcelain = false;
+    let mut interactive = false;
+    let mut macro_path = None;
+    let mut copy = false;
+    let mut sim = None;
+    let mut hist = false;
+    let mut at_least = None;
+    let mut at_most = None;
+    let mut separator = String::from("\t");
+    let mut exprs = vec![];
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--avrae" => avrae = true,
+            "--debug" => debug = true,
+            "--luck" => context.luck = true,
+            "--target" => {
+                let path = args.next().ok_or("Missing file for --target.")?;
+                context.defenses = Some(Defenses::load(&path)?);
+            }
+            "--porcelain" => porcelain = true,
+            "-q" | "--quiet" => context.quiet = true,
+            "--on-roll" => {
+                context.on_roll = Some(args.next().ok_or("Missing command for --on-roll.")?);
+            }
+            "--interactive" => interactive = true,
+            "--copy" => copy = true,
+            "--notify" => context.notify = true,
+            "--tally" => context.tally = true,
+            "--sets" => context.sets = true,
+            "--hist" => hist = true,
+            "--at-least" => {
+                let value = args.next().ok_or("Missing value for --at-least.")?;
+                let value = value
+                    .parse::<i32>()
+                    .map_err(|_| "Failed to parse --at-least.")?;
+                at_least = Some(value);
+            }
+            "--at-most" => {
+                let value = args.next().ok_or("Missing value for --at-most.")?;
+                let value = value
+                    .parse::<i32>()
+                    .map_err(|_| "Failed to parse --at-most.")?;
+                at_most = Some(value);
+            }
+            "--sim" => {
+                let times = args.next().ok_or("Missing number of rolls for --sim.")?;
+                let times = times
+                    .parse::<usize>()
+                    .map_err(|_| "Failed to parse --sim.")?;
+                if !(1..=sim::MAX_SIMULATIONS).contains(&times) {
+                    return Err(format!(
+                        "Values for --sim must be between 1 and {}.",
+                        sim::MAX_SIMULATIONS
+                    ));
+                }
+                sim = Some(times);
+            }
+            "--use" => {
+                let value = args.next().ok_or("Missing value for --use.")?;
+                let value = value.parse::<u32>().map_err(|_| "Failed to parse --use.")?;
+                if !(1..=20).contains(&value) {
+                    return Err("Values for --use must be between 1 and 20.".to_string());
+                }
+                context.portent = Some(value);
+            }
+            // Take a fixed result on every d20, e.g. `--take 10` for passive checks
+            "--take" => {
+                let value = args.next().ok_or("Missing value for --take.")?;
+                let value = value
+                    .parse::<u32>()
+                    .map_err(|_| "Failed to parse --take.")?;
+                if !(1..=20).contains(&value) {
+                    return Err("Values for --take must be between 1 and 20.".to_string());
+                }
+                context.take = Some(value);
+            }
+            // Seeded rolls are reproducible with the same build, though not across
+            // releases of rand, which may change the algorithm behind StdRng
+            // Cap how many times a single die may explode, e.g. `--max-explode 5`
+            "--max-explode" => {
+                let value = args.next().ok_or("Missing value for --max-explode.")?;
+                context.max_explosions = value
+                    .parse::<usize>()
+                    .map_err(|_| "Failed to parse --max-explode.")?;
+            }
+            "--seed" => {
+                let seed = args.next().ok_or("Missing value for --seed.")?;
+                let seed = seed.parse::<u64>().map_err(|_| "Failed to parse seed.")?;
+                context.rng = StdRng::seed_from_u64(seed);
+            }
+            "--separator" => {
+                separator = args
+                    .next()
+                    .ok_or("Missing value for --separator.")?
+                    .replace("\\t", "\t");
+            }
+            "--set" => {
+                let binding = args.next().ok_or("Missing binding for --set.")?;
+                context.set_constant(&binding)?;
+            }
+            "--macros" => {
+                macro_path = Some(args.next().ok_or("Missing file for --macros.")?);
+            }
+            "--sheet" => {
+                let path = args.next().ok_or("Missing file for --sheet.")?;
+                context.load_sheet(&path)?;
+            }
+            // Read expressions without the shell in the way, for notation full of `{}`, `!`
+            // and `>`
+            "--expr-file" => {
+                let path = args.next().ok_or("Missing file for --expr-file.")?;
+                let contents =
+                    fs::read_to_string(&path).map_err(|_| "Failed to read expression file.")?;
+                if contents.trim().is_empty() {
+                    return Err("Expression file is empty.".to_string());
+                }
+                exprs.push(contents.trim().to_string());
+            }
+            "-e" => {
+                if io::stdin().is_terminal() {
+                    eprint!("Expression: ");
+                    io::stderr()
+                        .flush()
+                        .map_err(|_| "Failed to write output.")?;
+                }
+                let mut line = String::new();
+                io::stdin()
+                    .read_line(&mut line)
+                    .map_err(|_| "Failed to read input.")?;
+                if line.trim().is_empty() {
+                    return Err("Missing expression for -e.".to_string());
+                }
+                exprs.push(line.trim().to_string());
+            }
+            _ => exprs.push(arg),
+        }
+    }
+
+    // `--debug` shows this crate's debug logs; otherwise `RUST_LOG` controls logging
+    let filter = if debug {
+        EnvFilter::new("roll=debug")
+    } else {
+        EnvFilter::from_default_env()
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .init();
+
+    if porcelain {
+        context.porcelain = Some(separator);
+    }
+    match exprs.first().map(String::as_str) {
+        Some("selftest") => return selftest(&exprs[1..]),
+        Some("scatter") => return Ok(scatter::run(exprs.into_iter().skip(1), &mut context.rng)?),
+        Some("pointbuy") => return Ok(pointbuy::run(exprs.into_iter().skip(1), &mut context.rng)?),
+        Some("off") => return Ok(rolloff::run(exprs.into_iter().skip(1), &mut context.rng)?),
+        Some("routine") => return Ok(routine::run(exprs.into_iter().skip(1), &mut context.rng)?),
+        Some("shop") => return Ok(shop::run(exprs.into_iter().skip(1), &mut context.rng)?),
+        Some("statblock") => {
+            return Ok(statblock::run(exprs.into_iter().skip(1), &mut context.rng)?)
+        }
+        _ => {}
+    }
+
+    context.load_macros(macro_path.as_deref())?;
+    match exprs.first().map(String::as_str) {
+        Some("hitloc") => {
+            let shape = exprs.get(1).ok_or("Missing creature shape for hitloc.")?;
+            return Ok(context.hit_location(&shape.to_lowercase())?);
+        }
+        Some("watch") => return context.watch(exprs.into_iter().skip(1)),
+        Some("inline") => return context.inline(&exprs[1..].join(" ")),
+        Some("lint") => {
+            context.lint(exprs.split_off(1));
+            return Ok(());
+        }
+        Some("normalize") => return context.print_canonical(&exprs[1..]),
+        Some("equiv") => return context.equivalent(&exprs[1..]),
+        Some("passive") => return Ok(context.passive(exprs.into_iter().skip(1))?),
+        Some("npc") => return Ok(context.npc(exprs.into_iter().skip(1))?),
+        Some("challenge") => return Ok(context.challenge(exprs.into_iter().skip(1))?),
+        Some("downtime") => return Ok(context.downtime(exprs.into_iter().skip(1))?),
+        Some("travel") => return Ok(context.travel(exprs.into_iter().skip(1))?),
+        // Options turn the `stats` macro into the stat generation workflow
+        Some("stats") if exprs.get(1).is_some_and(|arg| arg.starts_with("--")) => {
+            let stats: Vec<_> = context.macros["stats"]
+                .iter()
+                .map(|entry| entry.expr.clone())
+                .collect();
+            return Ok(stats::run(
+                &stats,
+                exprs.into_iter().skip(1),
+                &mut context.rng,
+            )?);
+        }
+        _ => {}
+    }
+
+    let piped = exprs.is_empty() && !io::stdin().is_terminal();
+    if !interactive && (piped || exprs == ["-"]) {
+        return Ok(context.batch()?);
+    }
+    if interactive || exprs.is_empty() {
+        return Ok(context.repl()?);
+    }
+
+    exprs = if avrae {
+        avrae::translate(exprs.into_iter().map(|expr| expr.to_lowercase()))?
+    } else {
+        normalize(exprs)
+    };
+    let rolls = context.parse_rolls(exprs.into_iter())?;
+
+    if !context.constants.is_empty() && context.porcelain.is_none() && !context.quiet {
+        let mut constants: Vec<_> = context.constants.iter().collect();
+        constants.sort();
+        let constants: Vec<_> = constants
+            .iter()
+            .map(|(name, value)| format!("{} = {}", name, value))
+            .collect();
+        println!("Constants: {}", constants.join(", "));
+    }
+    if at_least.is_some() || at_most.is_some() {
+        for entry in rolls {
+            sim::odds(&entry.expr, at_least, at_most, sim, &mut context.rng)?;
+        }
+        return Ok(());
+    }
+    if hist {
+        for entry in rolls {
+            hist::run(&entry.expr, sim, &mut context.rng)?;
+        }
+        return Ok(());
+    }
+    if let Some(times) = sim {
+        for entry in rolls {
+            sim::run(&entry.expr, times, &mut context.rng)?;
+        }
+        return Ok(());
+    }
+    if copy {
+        // Copy exactly what is printed, which is never colored
+        let mut output = vec![];
+        context
+            .process_rolls(rolls, &mut output, false)
+            .map_err(|_| "Failed to write output.")?;
+        let output = String::from_utf8_lossy(&output);
+        print!("{}", output);
+        copy_to_clipboard(&output)?;
+    } else {
+        context
+            .process_rolls(rolls, &mut io::stdout().lock(), stdout_color())
+            .map_err(|_| "Failed to write output.")?;
+    }
+
+    Ok(())
 }
 
 fn main() {
-    let mut context = Context::new();
-    context.load_macros();
-    match context.parse_rolls(env::args().skip(1)) {
-        Ok(rolls) => context.process_rolls(rolls),
-        Err(why) => println!("Error: {}", why),
+    if let Err(why) = run() {
+        println!("Error: {}", why);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_keeps_quoted_text() {
+        let args = vec![String::from(
+            r#"2D6 + 3  d6 {1-2: "Turn Left,  then GO", 3-6: "Stay"}"#,
+        )];
+        assert_eq!(
+            normalize(args),
+            vec!["2d6+3", "d6", r#"{1-2:"Turn Left,  then GO",3-6:"Stay"}"#]
+        );
+    }
+
+    #[test]
+    fn reordered_operands_are_equivalent() {
+        let context = Context::new(StdRng::seed_from_u64(0));
+        let equiv = |a, b| {
+            equivalent(
+                &context.parse_canonical(a).unwrap(),
+                &context.parse_canonical(b).unwrap(),
+            )
+        };
+        assert!(equiv("d20+d4", "d4+d20"));
+        assert!(equiv("2*d6+d4", "d4+d6*2"));
+        assert!(equiv("{d20,d20}h1+d4", "d4+{d20,d20}h1"));
+        assert!(!equiv("2d6", "d12"));
+        assert!(!equiv("{d20,d20}h1+d4", "{d20,d20}l1+d4"));
+    }
+
+    #[test]
+    fn labels_follow_hash_or_brackets() {
+        let context = Context::new(StdRng::seed_from_u64(0));
+        let parse = |input: &str| {
+            let tokens = normalize(vec![input.to_string()]);
+            let mut rolls = context.parse_rolls(tokens.into_iter()).unwrap();
+            assert_eq!(rolls.len(), 1, "{}", input);
+            let entry = rolls.remove(0);
+            (entry.expr.to_string(), entry.label)
+        };
+        let attack = (String::from("d20+7"), Some(String::from("attack")));
+        let sneak = (String::from("d20+7"), Some(String::from("sneak attack")));
+        assert_eq!(parse("1d20+7[attack]"), attack);
+        assert_eq!(parse("1d20+7 # attack"), attack);
+        assert_eq!(parse("1d20+7 # sneak attack"), sneak);
+        assert_eq!(parse("1d20+7#sneak attack"), sneak);
+        assert_eq!(parse("2d6[fire]+3"), (String::from("2d6[fire]+3"), None));
+    }
+
+    #[test]
+    fn totals_saturate() {
+        let mut context = Context::new(StdRng::seed_from_u64(0));
+        let args = ["2147483647", "1"].iter().map(|arg| arg.to_string());
+        let rolls = context.parse_rolls(args).unwrap();
+        let mut output = vec![];
+        context.process_rolls(rolls, &mut output, false).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.ends_with("Total: 2147483647\n"));
+    }
+
+    #[test]
+    fn max_explode_caps_explosions() {
+        let mut context = Context::new(StdRng::seed_from_u64(0));
+        context.max_explosions = 0;
+        let rolls = context
+            .parse_rolls(iter::once(String::from("10d2!")))
+            .unwrap();
+        let mut output = vec![];
+        context.process_rolls(rolls, &mut output, false).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("(explosion cap reached)"));
+    }
+
+    #[test]
+    fn watch_results_are_not_colored() {
+        let dir = env::temp_dir().join(format!("roll-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let request = dir.join("attack.roll");
+        fs::write(&request, "d20+5 4d6h3 3d6!").unwrap();
+
+        let mut context = Context::new(StdRng::seed_from_u64(0));
+        context.answer(&request).unwrap();
+        let result = fs::read_to_string(dir.join("attack.result")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.starts_with("d20+5: "));
+        assert!(!result.contains('\x1b'));
+        assert!(!request.exists());
+    }
+
+    #[test]
+    fn bad_watch_requests_are_answered() {
+        let dir = env::temp_dir().join(format!("roll-watch-bad-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut context = Context::new(StdRng::seed_from_u64(0));
+        for (name, contents) in [("typo", &b"2d6+"[..]), ("binary", &b"\xff\xfe"[..])] {
+            let request = dir.join(name).with_extension("roll");
+            fs::write(&request, contents).unwrap();
+            context.answer(&request).unwrap();
+            let result = fs::read_to_string(request.with_extension("result")).unwrap();
+            assert!(result.starts_with("Error: "), "{}", result);
+            assert!(!request.exists());
+        }
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,112 @@
+use rand::prelude::*;
+use roll::Roll;
+use std::fmt;
+
+const ABILITIES: [&str; 6] = ["STR", "DEX", "CON", "INT", "WIS", "CHA"];
+const STANDARD_ARRAY: [i32; 6] = [15, 14, 13, 12, 10, 8];
+
+/// A quickly generated statblock for a non-player character.
+pub struct Npc {
+    kind: String,
+    cr: String,
+    scores: Vec<i32>,
+    hit_dice: Roll,
+    hp: i32,
+    proficiency: i32,
+}
+
+fn modifier(score: i32) -> i32 {
+    (score - 10).div_euclid(2)
+}
+
+/// Parses a challenge rating such as `2` or `1/4` into a number.
+fn parse_cr(cr: &str) -> Result<f64, &'static str> {
+    let value = match cr {
+        "1/8" => 0.125,
+        "1/4" => 0.25,
+        "1/2" => 0.5,
+        _ => cr
+            .parse::<u32>()
+            .map_err(|_| "Failed to parse challenge rating.")? as f64,
+    };
+    if value > 30.0 {
+        return Err("Challenge ratings above 30 are not supported.");
+    }
+    Ok(value)
+}
+
+/// Returns the hit die size typical for a creature type.
+fn hit_die(kind: &str) -> u32 {
+    match kind {
+        "giant" | "dragon" => 12,
+        "fiend" | "monstrosity" | "elemental" => 10,
+        "fey" | "construct" => 6,
+        _ => 8,
+    }
+}
+
+impl fmt::Display for Npc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} (CR {})", self.kind, self.cr)?;
+        let scores: Vec<_> = ABILITIES
+            .iter()
+            .zip(self.scores.iter())
+            .map(|(ability, &score)| format!("{} {} ({:+})", ability, score, modifier(score)))
+            .collect();
+        writeln!(f, "{}", scores.join("  "))?;
+        writeln!(f, "HP: {} ({})", self.hp, self.hit_dice)?;
+        write!(f, "Proficiency: {:+}", self.proficiency)
+    }
+}
+
+impl Npc {
+    /// Generates an NPC of the given creature type and challenge rating. Abilities are rolled
+    /// with 3d6 in order, or shuffled from the standard array when `standard` is set.
+    pub fn generate(
+        kind: &str,
+        cr: &str,
+        standard: bool,
+        mut rng: impl Rng,
+    ) -> Result<Npc, &'static str> {
+        let rating = parse_cr(cr)?;
+
+        let scores = if standard {
+            let mut scores = STANDARD_ARRAY.to_vec();
+            scores.shuffle(&mut rng);
+            scores
+        } else {
+            let roll = Roll::new(3, 6, None, None, None);
+            (0..ABILITIES.len())
+                .map(|_| roll.roll(&mut rng).total())
+                .collect()
+        };
+
+        // Roughly two hit dice per challenge rating, plus the constitution bonus per die
+        let num_dice = (rating * 2.0).ceil().max(1.0) as u32 + 1;
+        let con = modifier(scores[2]);
+        let hit_dice = Roll::new(
+            num_dice,
+            hit_die(kind),
+            None,
+            None,
+            Some(con * num_dice as i32),
+        );
+        let hp = hit_dice.roll(&mut rng).total().max(1);
+        let proficiency = 2 + (rating.max(1.0) as i32 - 1) / 4;
+
+        let mut chars = kind.chars();
+        let kind = match chars.next() {
+            Some(first) => first.to_uppercase().chain(chars).collect(),
+            None => String::new(),
+        };
+
+        Ok(Npc {
+            kind,
+            cr: cr.to_string(),
+            scores,
+            hit_dice,
+            hp,
+            proficiency,
+        })
+    }
+}
@@ -0,0 +1,125 @@
+use rand::prelude::*;
+
+const MIN_SCORE: u32 = 8;
+const MAX_SCORE: u32 = 15;
+const DEFAULT_BUDGET: u32 = 27;
+const NUM_SCORES: usize = 6;
+
+/// Returns the 5e point-buy cost of a single ability score.
+fn cost(score: u32) -> Result<u32, &'static str> {
+    match score {
+        8..=13 => Ok(score - MIN_SCORE),
+        14 => Ok(7),
+        15 => Ok(9),
+        _ => Err("Point-buy scores must be between 8 and 15."),
+    }
+}
+
+fn total_cost(scores: &[u32]) -> Result<u32, &'static str> {
+    scores.iter().map(|&score| cost(score)).sum()
+}
+
+/// Returns the cost of raising a score by one point.
+fn step_cost(score: u32) -> u32 {
+    cost(score + 1).unwrap() - cost(score).unwrap()
+}
+
+/// Generates a random array by repeatedly raising a random score while the budget allows.
+fn random_array(budget: u32, mut rng: impl Rng) -> Vec<u32> {
+    let mut scores = vec![MIN_SCORE; NUM_SCORES];
+    let mut spent = 0;
+    loop {
+        let affordable: Vec<_> = (0..scores.len())
+            .filter(|&i| scores[i] < MAX_SCORE && spent + step_cost(scores[i]) <= budget)
+            .collect();
+        match affordable.choose(&mut rng) {
+            Some(&i) => {
+                spent += step_cost(scores[i]);
+                scores[i] += 1;
+            }
+            None => break,
+        }
+    }
+    scores.sort_unstable_by(|a, b| b.cmp(a));
+    scores
+}
+
+fn format_scores(scores: &[u32]) -> String {
+    let scores: Vec<_> = scores.iter().map(|score| score.to_string()).collect();
+    scores.join(", ")
+}
+
+/// Validates a point-buy array (`pointbuy 15 14 13 12 10 8`) or generates a random legal
+/// one (`pointbuy --random`), optionally against a custom `--budget`.
+pub fn run(mut args: impl Iterator<Item = String>, rng: impl Rng) -> Result<(), &'static str> {
+    let mut budget = DEFAULT_BUDGET;
+    let mut random = false;
+    let mut scores = vec![];
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--budget" => {
+                budget = args
+                    .next()
+                    .ok_or("Missing amount for --budget.")?
+                    .parse()
+                    .map_err(|_| "Failed to parse budget.")?;
+            }
+            "--random" => random = true,
+            score => {
+                let score = score
+                    .parse::<u32>()
+                    .map_err(|_| "Failed to parse ability score.")?;
+                scores.push(score);
+            }
+        }
+    }
+
+    if random {
+        if !scores.is_empty() {
+            return Err("Cannot combine --random with explicit scores.");
+        }
+        scores = random_array(budget, rng);
+    } else if scores.is_empty() {
+        return Err("No ability scores specified.");
+    } else if scores.len() != NUM_SCORES {
+        return Err("Point-buy arrays must have exactly six ability scores.");
+    }
+
+    let cost = total_cost(&scores)?;
+    println!("Scores: {}", format_scores(&scores));
+    if cost <= budget {
+        println!("Cost: {} / {} (valid)", cost, budget);
+    } else {
+        println!(
+            "Cost: {} / {} (over budget by {})",
+            cost,
+            budget,
+            cost - budget
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+
+    fn run_with(args: &[&str]) -> Result<(), &'static str> {
+        let args = args.iter().map(|arg| arg.to_string());
+        run(args, StdRng::seed_from_u64(0))
+    }
+
+    #[test]
+    fn requires_six_scores() {
+        let error = "Point-buy arrays must have exactly six ability scores.";
+        assert_eq!(run_with(&["15", "14", "13", "12", "10"]), Err(error));
+        assert_eq!(
+            run_with(&["15", "14", "13", "12", "10", "8", "8"]),
+            Err(error)
+        );
+        assert_eq!(run_with(&["15", "14", "13", "12", "10", "8"]), Ok(()));
+    }
+}
@@ -0,0 +1,88 @@
+/// Maps ranges of roll totals to text, e.g. `{1-2: "left", 3-4: "straight", 5-6: "right"}`.
+#[derive(Clone, Debug)]
+pub struct ResultMap {
+    entries: Vec<(i32, i32, String)>,
+}
+
+/// Splits on commas that are not inside a quoted string.
+fn split_entries(input: &str) -> Vec<&str> {
+    let mut entries = vec![];
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in input.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                entries.push(&input[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    entries.push(&input[start..]);
+    entries
+}
+
+/// Parses a range such as `3` or `1-2`.
+fn parse_range(input: &str) -> Result<(i32, i32), &'static str> {
+    let input = input.trim();
+
+    // Skip a leading sign so negative bounds are not mistaken for the separator
+    let split = input
+        .char_indices()
+        .skip(1)
+        .find(|&(_, c)| c == '-')
+        .map(|(i, _)| i);
+    let (low, high) = match split {
+        Some(i) => (&input[..i], &input[i + 1..]),
+        None => (input, input),
+    };
+    let low = low
+        .trim()
+        .parse::<i32>()
+        .map_err(|_| "Failed to parse result map range.")?;
+    let high = high
+        .trim()
+        .parse::<i32>()
+        .map_err(|_| "Failed to parse result map range.")?;
+    if low > high {
+        return Err("Result map ranges must be ascending.");
+    }
+    Ok((low, high))
+}
+
+impl ResultMap {
+    pub fn parse(input: &str) -> Result<ResultMap, &'static str> {
+        let inner = input
+            .trim()
+            .strip_prefix('{')
+            .and_then(|inner| inner.strip_suffix('}'))
+            .ok_or("Result maps must be enclosed in braces.")?;
+
+        let mut entries = vec![];
+        for entry in split_entries(inner) {
+            let mut iter = entry.splitn(2, ':');
+            let range = iter.next().unwrap_or("");
+            let text = iter
+                .next()
+                .ok_or("Result map entries must be of the form range: text.")?;
+            let (low, high) = parse_range(range)?;
+            let text = text.trim();
+            let text = text
+                .strip_prefix('"')
+                .and_then(|text| text.strip_suffix('"'))
+                .unwrap_or(text);
+            entries.push((low, high, text.to_string()));
+        }
+
+        Ok(ResultMap { entries })
+    }
+
+    /// Returns the text mapped to the given total, if any range contains it.
+    pub fn get(&self, total: i32) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(low, high, _)| (*low..=*high).contains(&total))
+            .map(|(_, _, text)| text.as_str())
+    }
+}
@@ -1,94 +1,517 @@
+use crate::error::RollError;
 use rand::prelude::*;
-use regex::Regex;
-use std::{fmt, str};
+use regex::{Captures, Match, Regex};
+use std::{collections::BTreeMap, fmt, ops::Range, slice, str};
 
-pub const REGEX_STR: &'static str = r"(?P<num>[0-9]*)d(?P<die>[0-9]+)(r(?P<reroll>[0-9]+))?((?P<high_or_low>[hl])(?P<keep>[0-9]+))?(?P<modifier>[\+\-][0-9]+)?";
+const DICE_REGEX_STR: &str = r"(?P<num>[0-9]*)d(?P<die>[0-9]+|[fF]|%)(\{(?P<weights>[0-9:\.,]*)\})?((?P<explode>!!|!p|!)((?P<explode_on>[<>]=?)?(?P<explode_num>[0-9]+))?)?(d(?P<drop>[hl])(?P<drop_num>[0-9]+)|(?P<advantage>aa|dd|adv|dis|a|d))?(r(?P<reroll>[0-9]+))?(k?(?P<high_or_low>[hl])(?P<keep>[0-9]+))?((?P<target>[<>]=?)(?P<target_num>[0-9]+)(f(?P<failure>[<>]=?)?(?P<failure_num>[0-9]+))?)?(\[(?P<each>[\+\-][0-9]+) ?each\])?";
+const MODIFIER_REGEX_STR: &str = r"(?P<modifier>([\+\-][0-9]+)+)?";
 
 lazy_static! {
-    static ref REGEX: Regex = Regex::new(REGEX_STR).unwrap();
+    static ref REGEX: Regex =
+        Regex::new(&format!("{}{}", DICE_REGEX_STR, MODIFIER_REGEX_STR)).unwrap();
+    static ref PREFIX_REGEX: Regex = Regex::new(&format!("^{}", DICE_REGEX_STR)).unwrap();
+    static ref MODIFIER_REGEX: Regex = Regex::new(r"[\+\-][0-9]+").unwrap();
 }
 
-#[derive(Clone, Debug)]
+// ANSI escape codes for colored outcomes
+const DROPPED: &str = "\x1b[2;9m";
+const GREY: &str = "\x1b[90m";
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+/// The most times a single die may explode when rolled directly, so that unlucky streaks
+/// stay bounded. Rolls through an `Engine` use `Limits::max_explosions` instead.
+pub const MAX_EXPLOSIONS: usize = 100;
+
+/// Explosion chains less likely than this are left out of exact distributions.
+const NEGLIGIBLE: f64 = 1e-15;
+
+/// Which dice of a roll count towards its total.
+#[derive(Clone, Debug, PartialEq)]
 pub enum Keep {
     High(usize),
     Low(usize),
+    DropHigh(usize),
+    DropLow(usize),
+}
+
+impl fmt::Display for Keep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Keep::High(n) => write!(f, "h{}", n),
+            Keep::Low(n) => write!(f, "l{}", n),
+            Keep::DropHigh(n) => write!(f, "dh{}", n),
+            Keep::DropLow(n) => write!(f, "dl{}", n),
+        }
+    }
+}
+
+impl Keep {
+    /// Returns the indices of the dice kept out of `len` dice sorted in ascending order.
+    pub fn range(&self, len: usize) -> Range<usize> {
+        match *self {
+            Keep::High(n) => len.saturating_sub(n)..len,
+            Keep::Low(n) => 0..n.min(len),
+            Keep::DropHigh(n) => 0..len.saturating_sub(n),
+            Keep::DropLow(n) => n.min(len)..len,
+        }
+    }
 }
 
+/// The result of rolling a `Roll`.
 #[derive(Clone, Debug)]
 pub struct Outcome {
     rolls: Vec<DieRoll>,
+    faces: Option<u32>,
+    tag: Option<String>,
+    capped: bool,
+    each: i32,
     modifier: i32,
     keep: Option<Keep>,
+    target: Option<Target>,
+    failure: Option<Target>,
+}
+
+/// A target number that each die is compared against in a success pool, e.g. `6d10>7`, or
+/// that marks a die as a failure, e.g. the `1` in `6d10>7f1`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    Equal(i32),
+    Greater(i32),
+    GreaterEqual(i32),
+    Less(i32),
+    LessEqual(i32),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Equal(n) => write!(f, "{}", n),
+            Target::Greater(n) => write!(f, ">{}", n),
+            Target::GreaterEqual(n) => write!(f, ">={}", n),
+            Target::Less(n) => write!(f, "<{}", n),
+            Target::LessEqual(n) => write!(f, "<={}", n),
+        }
+    }
 }
 
+/// Pluralizes a count of successes or failures.
+fn count(n: usize, singular: &str, plural: &str) -> String {
+    if n == 1 {
+        format!("1 {}", singular)
+    } else {
+        format!("{} {}", n, plural)
+    }
+}
+
+/// Parses a comparison and number such as `>=`, `7` into a target; no comparison means equal.
+fn parse_target(comparison: &str, num: &str) -> Result<Target, &'static str> {
+    let num = num
+        .parse::<i32>()
+        .map_err(|_| "Failed to parse target number.")?;
+    Ok(match comparison {
+        ">" => Target::Greater(num),
+        ">=" => Target::GreaterEqual(num),
+        "<" => Target::Less(num),
+        "<=" => Target::LessEqual(num),
+        _ => Target::Equal(num),
+    })
+}
+
+impl Target {
+    /// Returns whether a die value hits the target.
+    pub fn hits(&self, value: i32) -> bool {
+        match *self {
+            Target::Equal(n) => value == n,
+            Target::Greater(n) => value > n,
+            Target::GreaterEqual(n) => value >= n,
+            Target::Less(n) => value < n,
+            Target::LessEqual(n) => value <= n,
+        }
+    }
+}
+
+/// Whether an outcome's total is a sum of its dice or a count of successes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResultKind {
+    Sum,
+    Successes,
+}
+
+/// How a die that lands on its highest face, or on a face matching its trigger, is rolled
+/// again.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Explode {
+    /// Each extra roll is shown as part of a chain, e.g. `6!+6!+2`.
+    Add,
+    /// Extra rolls are compounded into a single value, e.g. `14!!`.
+    Compound,
+    /// Each extra roll adds one less than its value, e.g. `6!+6!+3-2`.
+    Penetrate,
+}
+
+/// A single rolled die, along with its original value if it was rerolled, or every roll in
+/// its chain if it exploded.
 #[derive(Clone, Debug)]
 pub enum DieRoll {
     Kept(u32),
     Rerolled(u32, u32),
+    Exploded(Vec<u32>),
+    Compounded(Vec<u32>),
+    Penetrated(Vec<u32>),
+    /// A Fate die, stored as its face from 1 to 3 and valued from -1 to +1.
+    Fate(u32),
+    /// A d100 read from a tens die and a units die.
+    Percentile(u32),
+    /// Several d6 read as the digits of one number, as for a d66.
+    Positional(Vec<u32>),
 }
 
 impl fmt::Display for DieRoll {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             DieRoll::Kept(n) => write!(f, "{}", n),
+            DieRoll::Fate(face) => match face {
+                1 => write!(f, "-"),
+                2 => write!(f, "0"),
+                _ => write!(f, "+"),
+            },
+            // A roll of 100 reads as 00 on the tens die and 0 on the units die
+            DieRoll::Percentile(n) => write!(f, "{:02}+{}", n % 100 / 10 * 10, n % 10),
+            DieRoll::Positional(_) => write!(f, "{}", self.value()),
             DieRoll::Rerolled(old, new) => write!(f, "{}=>{}", old, new),
+            DieRoll::Exploded(chain) | DieRoll::Penetrated(chain) => {
+                let (last, exploded) = chain.split_last().unwrap();
+                for n in exploded {
+                    write!(f, "{}!+", n)?;
+                }
+                write!(f, "{}", last)?;
+                if let DieRoll::Penetrated(_) = self {
+                    write!(f, "-{}", exploded.len())?;
+                }
+                Ok(())
+            }
+            DieRoll::Compounded(chain) => write!(f, "{}!!", chain.iter().sum::<u32>()),
         }
     }
 }
 
 impl DieRoll {
-    pub fn value(&self) -> u32 {
+    /// Returns the final value of the die.
+    pub fn value(&self) -> i32 {
+        match self {
+            DieRoll::Kept(n) => *n as i32,
+            DieRoll::Rerolled(_, n) => *n as i32,
+            DieRoll::Exploded(chain) | DieRoll::Compounded(chain) => {
+                chain.iter().sum::<u32>() as i32
+            }
+            DieRoll::Penetrated(chain) => {
+                chain.iter().sum::<u32>() as i32 - (chain.len() as i32 - 1)
+            }
+            DieRoll::Fate(face) => *face as i32 - 2,
+            DieRoll::Percentile(n) => *n as i32,
+            DieRoll::Positional(digits) => digits.iter().fold(0, |n, &d| n * 10 + d as i32),
+        }
+    }
+
+    /// Returns every roll that makes up the die's value, in the order they were rolled.
+    pub fn chain(&self) -> &[u32] {
         match self {
-            DieRoll::Kept(n) => *n,
-            DieRoll::Rerolled(_, n) => *n,
+            DieRoll::Kept(n)
+            | DieRoll::Rerolled(_, n)
+            | DieRoll::Fate(n)
+            | DieRoll::Percentile(n) => slice::from_ref(n),
+            DieRoll::Positional(digits) => digits,
+            DieRoll::Exploded(chain) | DieRoll::Compounded(chain) | DieRoll::Penetrated(chain) => {
+                chain
+            }
         }
     }
 }
 
 impl fmt::Display for Outcome {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} ", self.total())?;
-        let rolls: Vec<_> = self.rolls.iter().map(|roll| roll.to_string()).collect();
+        let total = self.total();
+        match (self.kind(), &self.failure) {
+            (ResultKind::Sum, _) => write!(f, "{} ", total)?,
+            (ResultKind::Successes, None) => {
+                write!(f, "{} ", count(self.successes(), "success", "successes"))?
+            }
+            (ResultKind::Successes, Some(_)) => {
+                write!(
+                    f,
+                    "{} - {} = {}",
+                    count(self.successes(), "success", "successes"),
+                    count(self.failures(), "failure", "failures"),
+                    total
+                )?;
+                if self.is_botch() {
+                    write!(f, ", botch")?;
+                }
+                write!(f, " ")?;
+            }
+        }
+
+        // Dropped dice are shown in brackets, successes are marked with an asterisk and
+        // failures with an `f`. The alternate flag (`{:#}`) adds color, dimming dropped dice
+        // and rerolled originals.
+        let color = f.alternate();
+        let kept = self.kept_range();
+        let rolls: Vec<_> = self
+            .rolls
+            .iter()
+            .enumerate()
+            .map(|(i, roll)| {
+                let text = match roll {
+                    _ if !color => roll.to_string(),
+                    DieRoll::Kept(n) => self.paint(*n),
+                    DieRoll::Rerolled(old, new) => {
+                        format!("{}{}{}=>{}", GREY, old, RESET, self.paint(*new))
+                    }
+                    _ => roll.to_string(),
+                };
+                if !kept.contains(&i) {
+                    if color {
+                        format!("{}[{}]{}", DROPPED, roll, RESET)
+                    } else {
+                        format!("[{}]", roll)
+                    }
+                } else if self.is_success(roll) {
+                    format!("{}*", text)
+                } else if self.is_failure(roll) {
+                    format!("{}f", text)
+                } else {
+                    text
+                }
+            })
+            .collect();
         let rolls = rolls.join(", ");
         write!(f, "({})", rolls)?;
+        if self.each > 0 {
+            write!(f, " + {} each", self.each)?;
+        } else if self.each < 0 {
+            write!(f, " - {} each", -self.each)?;
+        }
         if self.modifier > 0 {
-            write!(f, " + {}", self.modifier)
+            write!(f, " + {}", self.modifier)?;
         } else if self.modifier < 0 {
-            write!(f, " - {}", -self.modifier)
-        } else {
-            Ok(())
+            write!(f, " - {}", -self.modifier)?;
+        }
+        if let Some(tag) = &self.tag {
+            write!(f, " [{}]", tag)?;
+        }
+        if self.capped {
+            write!(f, " (explosion cap reached)")?;
         }
+        Ok(())
     }
 }
 
 impl Outcome {
-    pub fn new(mut rolls: Vec<DieRoll>, keep: Option<Keep>, modifier: i32) -> Outcome {
-        rolls.sort_by(|a, b| a.value().cmp(&b.value()));
+    pub fn new(
+        mut rolls: Vec<DieRoll>,
+        keep: Option<Keep>,
+        each: i32,
+        modifier: i32,
+        target: Option<Target>,
+        failure: Option<Target>,
+    ) -> Outcome {
+        rolls.sort_by_key(|roll| roll.value());
         Outcome {
             rolls,
+            faces: None,
+            tag: None,
+            capped: false,
             keep,
+            each,
             modifier,
+            target,
+            failure,
         }
     }
 
-    /// Computes the total value of the roll outcome.
+    /// Records the damage type of the roll that produced the outcome.
+    pub(crate) fn with_tag(mut self, tag: Option<String>) -> Outcome {
+        self.tag = tag;
+        self
+    }
+
+    /// Returns the damage type of the roll, e.g. `fire` for `2d6[fire]`.
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+
+    /// Records whether a die stopped exploding only because it reached the explosion cap.
+    pub(crate) fn with_capped(mut self, capped: bool) -> Outcome {
+        self.capped = capped;
+        self
+    }
+
+    /// Returns whether a die stopped exploding only because it reached the explosion cap,
+    /// in which case the total is lower than the dice alone would have given.
+    pub fn capped(&self) -> bool {
+        self.capped
+    }
+
+    /// Records the number of faces on the dice, so that maximum rolls can be highlighted.
+    pub(crate) fn with_faces(mut self, faces: u32) -> Outcome {
+        self.faces = Some(faces);
+        self
+    }
+
+    /// Formats a face for colored output: maximum rolls are green and ones are red.
+    fn paint(&self, face: u32) -> String {
+        match self.faces {
+            Some(faces) if face >= faces => format!("{}{}{}", GREEN, face, RESET),
+            Some(_) if face == 1 => format!("{}{}{}", RED, face, RESET),
+            _ => face.to_string(),
+        }
+    }
+
+    /// Returns whether the total is a sum of the dice or a count of successes.
+    pub fn kind(&self) -> ResultKind {
+        match self.target {
+            Some(_) => ResultKind::Successes,
+            None => ResultKind::Sum,
+        }
+    }
+
+    fn is_success(&self, roll: &DieRoll) -> bool {
+        self.target
+            .as_ref()
+            .is_some_and(|target| target.hits(roll.value() + self.each))
+    }
+
+    fn is_failure(&self, roll: &DieRoll) -> bool {
+        self.failure
+            .as_ref()
+            .is_some_and(|failure| failure.hits(roll.value() + self.each))
+    }
+
+    /// Returns the number of kept dice that hit the target, or zero if there is no target.
+    pub fn successes(&self) -> usize {
+        self.kept()
+            .iter()
+            .filter(|roll| self.is_success(roll))
+            .count()
+    }
+
+    /// Returns the number of kept dice that count as failures.
+    pub fn failures(&self) -> usize {
+        self.kept()
+            .iter()
+            .filter(|roll| self.is_failure(roll))
+            .count()
+    }
+
+    /// Returns whether a success pool rolled failures without a single success.
+    pub fn is_botch(&self) -> bool {
+        self.successes() == 0 && self.failures() > 0
+    }
+
+    /// Returns the rolled dice, sorted by value.
+    pub fn rolls(&self) -> &[DieRoll] {
+        &self.rolls
+    }
+
+    /// Returns the dice that count towards the total, sorted by value.
+    pub fn kept(&self) -> &[DieRoll] {
+        &self.rolls[self.kept_range()]
+    }
+
+    /// Returns the dice left out of the total by keeping or dropping, sorted by value.
+    pub fn dropped(&self) -> Vec<&DieRoll> {
+        let kept = self.kept_range();
+        self.rolls[..kept.start]
+            .iter()
+            .chain(&self.rolls[kept.end..])
+            .collect()
+    }
+
+    /// Returns each rerolled die as its original roll and the roll that replaced it.
+    pub fn rerolls(&self) -> Vec<(u32, u32)> {
+        self.rolls
+            .iter()
+            .filter_map(|roll| match *roll {
+                DieRoll::Rerolled(old, new) => Some((old, new)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the flat modifier added to the total.
+    pub fn modifier(&self) -> i32 {
+        self.modifier
+    }
+
+    /// Returns the bonus added to each kept die.
+    pub fn each(&self) -> i32 {
+        self.each
+    }
+
+    /// Counts how many dice landed on each value, dropped dice included, in ascending order
+    /// of value.
+    pub fn tally(&self) -> Vec<(i32, usize)> {
+        let mut counts = BTreeMap::new();
+        for roll in &self.rolls {
+            *counts.entry(roll.value()).or_insert(0) += 1;
+        }
+        counts.into_iter().collect()
+    }
+
+    /// Returns the sets of matching dice as (width, height) pairs, the number of dice and the
+    /// value they share, widest and then highest first, as read in the One Roll Engine.
+    pub fn sets(&self) -> Vec<(usize, i32)> {
+        matched_sets(&self.tally())
+    }
+
+    /// Returns whether the outcome adds flat or per-die modifiers to its dice.
+    pub(crate) fn has_modifier(&self) -> bool {
+        self.modifier != 0 || self.each != 0
+    }
+
+    fn kept_range(&self) -> Range<usize> {
+        match &self.keep {
+            Some(keep) => keep.range(self.rolls.len()),
+            None => 0..self.rolls.len(),
+        }
+    }
+
+    /// Computes the total value of the roll outcome. For a success pool, this is the number of
+    /// successes less the number of failures, plus the modifier.
     pub fn total(&self) -> i32 {
-        let range = match &self.keep {
-            Some(Keep::High(n)) => &self.rolls[self.rolls.len() - n..],
-            Some(Keep::Low(n)) => &self.rolls[..*n],
-            None => &self.rolls[..],
-        };
-        range.into_iter().map(|roll| roll.value()).sum::<u32>() as i32 + self.modifier
+        let range = self.kept();
+        if self.target.is_some() {
+            let net = self.successes() as i32 - self.failures() as i32;
+            return net.saturating_add(self.modifier);
+        }
+        let dice = range
+            .iter()
+            .fold(0i32, |total, roll| total.saturating_add(roll.value()));
+        dice.saturating_add(self.each.saturating_mul(range.len() as i32))
+            .saturating_add(self.modifier)
     }
 }
 
-#[derive(Clone, Debug)]
+/// A roll of identical dice in standard notation, e.g. `4d6h3+2`.
+#[derive(Clone, Debug, PartialEq)]
 pub struct Roll {
     num: u32,
     die: u32,
+    fate: bool,
+    percentile: bool,
+    digits: u32,
+    weights: Option<Vec<f64>>,
+    explode: Option<Explode>,
+    explode_on: Option<Target>,
     reroll: Option<u32>,
+    each: Option<i32>,
     modifier: Option<i32>,
     keep: Option<Keep>,
+    target: Option<Target>,
+    failure: Option<Target>,
+    tag: Option<String>,
 }
 
 impl fmt::Display for Roll {
@@ -97,23 +520,62 @@ impl fmt::Display for Roll {
             write!(f, "{}", self.num)?;
         }
 
-        write!(f, "d{}", self.die)?;
+        if self.fate {
+            write!(f, "dF")?;
+        } else if self.percentile {
+            write!(f, "d%")?;
+        } else if self.digits > 0 {
+            write!(f, "d{}", "6".repeat(self.digits as usize))?;
+        } else {
+            write!(f, "d{}", self.die)?;
+        }
+
+        if let Some(weights) = &self.weights {
+            let weights: Vec<_> = weights
+                .iter()
+                .enumerate()
+                .filter(|(_, &weight)| weight > 0.0)
+                .map(|(face, weight)| format!("{}:{}", face + 1, weight))
+                .collect();
+            write!(f, "{{{}}}", weights.join(","))?;
+        }
+
+        match self.explode {
+            Some(Explode::Add) => write!(f, "!")?,
+            Some(Explode::Compound) => write!(f, "!!")?,
+            Some(Explode::Penetrate) => write!(f, "!p")?,
+            None => {}
+        }
+        if let Some(trigger) = &self.explode_on {
+            write!(f, "{}", trigger)?;
+        }
 
         if let Some(n) = self.reroll {
             write!(f, "r{}", n)?;
         }
 
         if let Some(keep) = &self.keep {
-            match keep {
-                Keep::High(n) => {
-                    write!(f, "h{}", n)?;
-                }
-                Keep::Low(n) => {
-                    write!(f, "l{}", n)?;
-                }
+            write!(f, "{}", keep)?;
+        }
+
+        if let Some(target) = &self.target {
+            write!(f, "{}", target)?;
+        }
+
+        if let Some(failure) = &self.failure {
+            write!(f, "f{}", failure)?;
+        }
+
+        if let Some(each) = self.each {
+            if each != 0 {
+                write!(f, "[{:+} each]", each)?;
             }
         }
 
+        if let Some(tag) = &self.tag {
+            write!(f, "[{}]", tag)?;
+        }
+
         if let Some(modifier) = self.modifier {
             if modifier != 0 {
                 write!(f, "{:+}", modifier)?;
@@ -129,91 +591,423 @@ impl Default for Roll {
         Roll {
             num: 1,
             die: 0,
+            fate: false,
+            percentile: false,
+            digits: 0,
+            weights: None,
+            explode: None,
+            explode_on: None,
             reroll: None,
+            each: None,
             modifier: None,
             keep: None,
+            target: None,
+            failure: None,
+            tag: None,
         }
     }
 }
 
 impl str::FromStr for Roll {
-    type Err = &'static str;
+    type Err = RollError;
 
     fn from_str(input: &str) -> Result<Roll, Self::Err> {
-        if let Some(cap) = REGEX.captures(input) {
-            let mut roll = Roll::default();
-            if let Some(num) = cap.name("num") {
-                let num_str = &input[num.start()..num.end()];
-                if num_str.len() > 0 {
-                    let num_parsed = num_str
-                        .parse::<u32>()
-                        .map_err(|_| "Failed to parse number of dice.")?;
-                    roll.num = num_parsed;
-                }
-            }
-            if let Some(die) = cap.name("die") {
-                let die_str = &input[die.start()..die.end()];
-                let die_parsed = die_str
+        // The notation must cover the whole input, so point at any text left around it
+        let cap = REGEX
+            .captures(input)
+            .ok_or_else(|| RollError::parse(input, 0, "Invalid roll notation."))?;
+        let whole = cap.get(0).unwrap();
+        if whole.start() > 0 {
+            return Err(RollError::parse(input, 0, "Invalid roll notation."));
+        }
+        if whole.end() < input.len() {
+            return Err(RollError::parse(
+                input,
+                whole.end(),
+                "Invalid roll notation.",
+            ));
+        }
+        Roll::from_captures(input, &cap)
+    }
+}
+
+impl Roll {
+    /// Builds a roll from the captures of the dice notation regex.
+    fn from_captures(input: &str, cap: &Captures) -> Result<Roll, RollError> {
+        let parse_error = |position, reason| RollError::parse(input, position, reason);
+        let mut roll = Roll::default();
+        if let Some(num) = cap.name("num") {
+            let num_str = &input[num.start()..num.end()];
+            if !num_str.is_empty() {
+                let num_parsed = num_str
                     .parse::<u32>()
-                    .map_err(|_| "Failed to parse die size.")?;
-                roll.die = die_parsed;
-            } else {
-                return Err("No die specified.");
+                    .map_err(|_| parse_error(num.start(), "Failed to parse number of dice."))?;
+                roll.num = num_parsed;
+            }
+        }
+        if let Some(die) = cap.name("die") {
+            let die_str = &input[die.start()..die.end()];
+            if die_str.eq_ignore_ascii_case("f") {
+                // Fate dice are rolled as a d3 whose faces are read as -1, 0 and +1
+                roll.fate = true;
             }
-            if let Some(reroll) = cap.name("reroll") {
-                let reroll_str = &input[reroll.start()..reroll.end()];
-                let reroll_parsed = reroll_str
+            let die_parsed = if roll.fate {
+                3
+            } else if die_str == "%" {
+                roll.percentile = true;
+                100
+            } else if die_str.len() > 1 && die_str.chars().all(|c| c == '6') {
+                // A d66 or d666 reads each d6 as one digit of the result
+                if die_str.len() > MAX_DIGITS {
+                    return Err(RollError::InvalidDice(
+                        "Positional dice can have at most 9 digits.",
+                    ));
+                }
+                roll.digits = die_str.len() as u32;
+                6
+            } else {
+                die_str
                     .parse::<u32>()
-                    .map_err(|_| "Failed to parse reroll.")?;
-                roll.reroll = Some(reroll_parsed);
+                    .map_err(|_| parse_error(die.start(), "Failed to parse die size."))?
+            };
+            if die_parsed == 0 {
+                return Err(RollError::InvalidDice("Dice must have at least one face."));
+            }
+            roll.die = die_parsed;
+        } else {
+            return Err(parse_error(0, "No die specified."));
+        }
+        if roll.fate
+            && ["weights", "explode", "reroll"]
+                .iter()
+                .any(|name| cap.name(name).is_some())
+        {
+            return Err(RollError::InvalidDice(
+                "Fate dice cannot be weighted, exploded or rerolled.",
+            ));
+        }
+        if roll.digits > 0
+            && ["weights", "explode", "reroll", "target"]
+                .iter()
+                .any(|name| cap.name(name).is_some())
+        {
+            return Err(RollError::InvalidDice(
+                "Positional dice cannot be weighted, exploded, rerolled or pooled.",
+            ));
+        }
+        if let Some(weights) = cap.name("weights") {
+            roll.weights = Some(parse_weights(input, weights, roll.die)?);
+        }
+        if let Some(explode) = cap.name("explode") {
+            if roll.die < 2 {
+                return Err(RollError::InvalidDice(
+                    "Exploding dice must have at least two faces.",
+                ));
+            }
+            roll.explode = Some(match explode.as_str() {
+                "!!" => Explode::Compound,
+                "!p" => Explode::Penetrate,
+                _ => Explode::Add,
+            });
+        }
+        if let Some(explode_num) = cap.name("explode_num") {
+            // A custom trigger, e.g. the `>=5` of `d6!>=5`, replaces the highest face
+            let comparison = cap.name("explode_on").map_or("", |c| c.as_str());
+            let trigger = parse_target(comparison, explode_num.as_str())
+                .map_err(|reason| parse_error(explode_num.start(), reason))?;
+            let hits = (1..=roll.die as i32)
+                .filter(|&face| trigger.hits(face))
+                .count();
+            if hits == 0 {
+                return Err(RollError::InvalidDice(
+                    "Explosion triggers must match a face of the die.",
+                ));
+            }
+            if hits == roll.die as usize {
+                return Err(RollError::InvalidDice(
+                    "Explosion triggers must leave a face that does not explode.",
+                ));
             }
-            if let Some(modifier) = cap.name("modifier") {
-                let mod_str = &input[modifier.start()..modifier.end()];
-                let mod_parsed = mod_str
+            roll.explode_on = Some(trigger);
+        }
+        if let Some(reroll) = cap.name("reroll") {
+            let reroll_str = &input[reroll.start()..reroll.end()];
+            let reroll_parsed = reroll_str
+                .parse::<u32>()
+                .map_err(|_| parse_error(reroll.start(), "Failed to parse reroll."))?;
+            roll.reroll = Some(reroll_parsed);
+        }
+        if let Some(each) = cap.name("each") {
+            let each_str = &input[each.start()..each.end()];
+            let each_parsed = each_str
+                .parse::<i32>()
+                .map_err(|_| parse_error(each.start(), "Failed to parse per-die modifier."))?;
+            roll.each = Some(each_parsed);
+        }
+        if let Some(modifier) = cap.name("modifier") {
+            // Fold chains of constants such as `+3-1+2` into a single modifier
+            let mod_str = &input[modifier.start()..modifier.end()];
+            let mut mod_total = 0i32;
+            for term in MODIFIER_REGEX.find_iter(mod_str) {
+                let position = modifier.start() + term.start();
+                let term_parsed = term
+                    .as_str()
                     .parse::<i32>()
-                    .map_err(|_| "Failed to parse modifier.")?;
-                roll.modifier = Some(mod_parsed);
-            }
-            if let Some(high_or_low) = cap.name("high_or_low") {
-                let hol_str = &input[high_or_low.start()..high_or_low.end()];
-                let is_high = match hol_str {
-                    "h" => true,
-                    "l" => false,
-                    _ => {
-                        return Err("Error parsing high or low.");
-                    }
-                };
-                if let Some(keep_amount) = cap.name("keep") {
-                    let keep_str = &input[keep_amount.start()..keep_amount.end()];
-                    let keep_parsed = keep_str
-                        .parse::<usize>()
-                        .map_err(|_| "Error parsing number or dice to keep.")?;
-                    let keep = if is_high {
-                        Keep::High(keep_parsed)
-                    } else {
-                        Keep::Low(keep_parsed)
-                    };
-                    roll.keep = Some(keep);
+                    .map_err(|_| parse_error(position, "Failed to parse modifier."))?;
+                mod_total = mod_total
+                    .checked_add(term_parsed)
+                    .ok_or(RollError::LimitExceeded("Modifier is too large."))?;
+            }
+            roll.modifier = Some(mod_total);
+        }
+        if let Some(high_or_low) = cap.name("high_or_low") {
+            let hol_str = &input[high_or_low.start()..high_or_low.end()];
+            let is_high = match hol_str {
+                "h" => true,
+                "l" => false,
+                _ => {
+                    return Err(parse_error(
+                        high_or_low.start(),
+                        "Error parsing high or low.",
+                    ));
                 }
+            };
+            if let Some(keep_amount) = cap.name("keep") {
+                let keep_str = &input[keep_amount.start()..keep_amount.end()];
+                let keep_parsed = keep_str.parse::<usize>().map_err(|_| {
+                    parse_error(keep_amount.start(), "Error parsing number or dice to keep.")
+                })?;
+                let keep = if is_high {
+                    Keep::High(keep_parsed)
+                } else {
+                    Keep::Low(keep_parsed)
+                };
+                roll.keep = Some(keep);
             }
-            Ok(roll)
-        } else {
-            println!("{}", input);
-            Err("Something went wrong.")
         }
+        if let Some(target) = cap.name("target") {
+            let parsed = parse_target(target.as_str(), &cap["target_num"])
+                .map_err(|reason| parse_error(target.start(), reason))?;
+            roll.target = Some(parsed);
+        }
+        if let Some(failure) = cap.name("failure_num") {
+            let comparison = cap.name("failure").map_or("", |c| c.as_str());
+            let parsed = parse_target(comparison, failure.as_str())
+                .map_err(|reason| parse_error(failure.start(), reason))?;
+            roll.failure = Some(parsed);
+        }
+        if let Some(drop) = cap.name("drop") {
+            if roll.keep.is_some() {
+                return Err(RollError::InvalidDice("Cannot both keep and drop dice."));
+            }
+            let drop_parsed = cap["drop_num"]
+                .parse::<usize>()
+                .map_err(|_| parse_error(drop.start(), "Error parsing number of dice to drop."))?;
+            roll.keep = Some(match drop.as_str() {
+                "h" => Keep::DropHigh(drop_parsed),
+                _ => Keep::DropLow(drop_parsed),
+            });
+        }
+        if let Some(advantage) = cap.name("advantage") {
+            if roll.num != 1 {
+                return Err(RollError::InvalidDice(
+                    "Advantage and disadvantage apply to a single die.",
+                ));
+            }
+            if roll.keep.is_some() {
+                return Err(RollError::InvalidDice(
+                    "Advantage and disadvantage cannot be combined with keep.",
+                ));
+            }
+            let (num, keep) = match advantage.as_str() {
+                "aa" => (3, Keep::High(1)),
+                "dd" => (3, Keep::Low(1)),
+                "a" | "adv" => (2, Keep::High(1)),
+                _ => (2, Keep::Low(1)),
+            };
+            roll.num = num;
+            roll.keep = Some(keep);
+        }
+        Ok(roll)
+    }
+
+    /// Parses dice notation at the start of the input, without any trailing modifier,
+    /// returning the roll and the length of the notation.
+    pub(crate) fn parse_prefix(input: &str) -> Result<Option<(Roll, usize)>, RollError> {
+        match PREFIX_REGEX.captures(input) {
+            Some(cap) => Ok(Some((Roll::from_captures(input, &cap)?, cap[0].len()))),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The most digits a positional die may have, so that its value fits in an `i32`.
+const MAX_DIGITS: usize = 9;
+
+/// The most faces a weighted die may have, since every face is given a weight.
+const MAX_WEIGHTED_FACES: u32 = 1_000;
+
+/// Parses per-face weights such as `1:0.1,6:0.5` from the `weights` span of `input`. Faces
+/// that are not listed have no weight.
+fn parse_weights(input: &str, weights: Match, die: u32) -> Result<Vec<f64>, RollError> {
+    let parse_error = |reason| RollError::parse(input, weights.start(), reason);
+    if die > MAX_WEIGHTED_FACES {
+        return Err(parse_error("Weighted dice can have at most 1000 faces."));
+    }
+    let mut parsed = vec![0.0; die as usize];
+    for entry in weights.as_str().split(',') {
+        let mut iter = entry.splitn(2, ':');
+        let face = iter
+            .next()
+            .unwrap_or("")
+            .parse::<u32>()
+            .map_err(|_| parse_error("Failed to parse weighted face."))?;
+        let weight = iter
+            .next()
+            .ok_or_else(|| parse_error("Weights must be of the form face:weight."))?
+            .parse::<f64>()
+            .map_err(|_| parse_error("Failed to parse face weight."))?;
+        if face == 0 || face > die {
+            return Err(parse_error("Weighted face is not on the die."));
+        }
+        if !weight.is_finite() || weight < 0.0 {
+            return Err(parse_error("Face weights must be non-negative."));
+        }
+        parsed[face as usize - 1] = weight;
+    }
+    let total = parsed.iter().sum::<f64>();
+    if total <= 0.0 {
+        return Err(RollError::InvalidDice(
+            "At least one face must have a positive weight.",
+        ));
     }
+    if !total.is_finite() {
+        return Err(RollError::InvalidDice(
+            "Face weights are too large to add up.",
+        ));
+    }
+    Ok(parsed)
+}
+
+/// Finds the sets of matching dice in a tally of faces, widest and then highest first.
+pub(crate) fn matched_sets(tally: &[(i32, usize)]) -> Vec<(usize, i32)> {
+    let mut sets: Vec<_> = tally
+        .iter()
+        .filter(|&&(_, count)| count > 1)
+        .map(|&(value, count)| (count, value))
+        .collect();
+    sets.sort_by(|a, b| b.cmp(a));
+    sets
 }
 
-fn expected_roll(die: u32, reroll: Option<u32>) -> f64 {
-    let reroll = reroll.unwrap_or(die + 1);
-    let avg = (die as f64 / 2.0) + 0.5;
-    let total = (1..=die)
-        .map(|n| if n <= reroll { avg } else { n as f64 })
+/// Rounds away the noise that summing many inexact probabilities leaves in the last digits,
+/// such as 4.199999999999997 for the mean of `1d6!`.
+fn round_noise(value: f64) -> f64 {
+    (value * 1e12).round() / 1e12
+}
+
+/// Computes the variance of a distribution of totals.
+fn variance(dist: &BTreeMap<i32, f64>) -> f64 {
+    let mean = dist
+        .iter()
+        .map(|(&total, &p)| total as f64 * p)
         .sum::<f64>();
-    total / (die as f64)
+    dist.iter()
+        .map(|(&total, &p)| (total as f64 - mean).powi(2) * p)
+        .sum::<f64>()
+}
+
+/// Computes the distribution of the sum of two independent totals.
+fn convolve(a: &BTreeMap<i32, f64>, b: &BTreeMap<i32, f64>) -> BTreeMap<i32, f64> {
+    let mut sum = BTreeMap::new();
+    for (&x, &p) in a {
+        for (&y, &q) in b {
+            *sum.entry(x.saturating_add(y)).or_insert(0.0) += p * q;
+        }
+    }
+    sum
+}
+
+/// Computes the distribution of the sum of `num` independent totals with distribution `die`.
+fn convolve_n(die: &BTreeMap<i32, f64>, mut num: u32) -> BTreeMap<i32, f64> {
+    let mut sum = BTreeMap::new();
+    sum.insert(0, 1.0);
+    let mut power = die.clone();
+    while num > 0 {
+        if num & 1 == 1 {
+            sum = convolve(&sum, &power);
+        }
+        num >>= 1;
+        if num > 0 {
+            power = convolve(&power, &power);
+        }
+    }
+    sum
+}
+
+/// Returns the chance that at least `k` of `n` independent events of chance `p` happen, for
+/// each `k` from 0 to `n`.
+fn binomial_tails(n: usize, p: f64) -> Vec<f64> {
+    let mut chances = vec![0.0; n + 1];
+    if p <= 0.0 {
+        chances[0] = 1.0;
+    } else if p >= 1.0 {
+        chances[n] = 1.0;
+    } else {
+        // Work in logarithms so that large pools do not underflow
+        let mut ln_choose = 0.0;
+        for (k, chance) in chances.iter_mut().enumerate() {
+            if k > 0 {
+                ln_choose += ((n - k + 1) as f64 / k as f64).ln();
+            }
+            *chance = (ln_choose + k as f64 * p.ln() + (n - k) as f64 * (1.0 - p).ln()).exp();
+        }
+    }
+    let mut tail = 0.0;
+    for chance in chances.iter_mut().rev() {
+        tail += *chance;
+        *chance = tail;
+    }
+    chances
+}
+
+/// Computes the distribution of the total of the dice at the `kept` positions when `num` dice
+/// with distribution `die` are sorted in ascending order, scoring each kept die by `score`.
+fn kept_distribution(
+    die: &BTreeMap<i32, f64>,
+    num: usize,
+    kept: Range<usize>,
+    score: impl Fn(i32) -> i32,
+) -> BTreeMap<i32, f64> {
+    // `placed[j]` is the distribution of the total so far once the lowest `j` dice are known.
+    // Each value in turn takes the next `c` positions, in any of `C(num - j, c)` orders.
+    let mut placed: Vec<BTreeMap<i32, f64>> = vec![BTreeMap::new(); num + 1];
+    placed[0].insert(0, 1.0);
+    for (&value, &p) in die {
+        let mut next = vec![BTreeMap::new(); num + 1];
+        for (j, totals) in placed.iter().enumerate() {
+            for (&total, &q) in totals {
+                let mut chance = q;
+                for c in 0..=num - j {
+                    if c > 0 {
+                        chance *= p * (num - j - c + 1) as f64 / c as f64;
+                        if chance == 0.0 {
+                            break;
+                        }
+                    }
+                    let counted = (j + c).min(kept.end).saturating_sub(j.max(kept.start)) as i32;
+                    let total = total.saturating_add(counted.saturating_mul(score(value)));
+                    *next[j + c].entry(total).or_insert(0.0) += chance;
+                }
+            }
+        }
+        placed = next;
+    }
+    placed.pop().unwrap_or_default()
 }
 
 impl Roll {
+    /// Creates a roll of `num` dice with `die` faces.
     pub fn new(
         num: u32,
         die: u32,
@@ -224,33 +1018,412 @@ impl Roll {
         Roll {
             num,
             die,
+            fate: false,
+            percentile: false,
+            digits: 0,
+            weights: None,
+            explode: None,
+            explode_on: None,
             reroll,
+            each: None,
             keep,
             modifier,
+            target: None,
+            failure: None,
+            tag: None,
+        }
+    }
+
+    /// Returns the damage type of the roll, e.g. `fire` for `2d6[fire]`.
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+
+    /// Sets the damage type of the roll.
+    pub(crate) fn set_tag(&mut self, tag: &str) {
+        self.tag = Some(tag.to_string());
+    }
+
+    /// Returns how many dice the roll throws before rerolls and explosions, counting each
+    /// digit of a positional die, as a d66 throws two d6.
+    pub(crate) fn dice_rolled(&self) -> u32 {
+        self.num.saturating_mul(self.digits.max(1))
+    }
+
+    pub(crate) fn die(&self) -> u32 {
+        self.die
+    }
+
+    /// Returns whether the roll adds a flat modifier to its dice.
+    pub(crate) fn has_modifier(&self) -> bool {
+        self.modifier.is_some_and(|modifier| modifier != 0)
+    }
+
+    /// Adds to the roll's flat modifier.
+    pub(crate) fn add_modifier(&mut self, amount: i32) -> Result<(), RollError> {
+        let modifier = self
+            .modifier
+            .unwrap_or(0)
+            .checked_add(amount)
+            .ok_or(RollError::LimitExceeded("Modifier is too large."))?;
+        self.modifier = Some(modifier);
+        Ok(())
+    }
+
+    /// Combines two rolls of the same kind of dice into one, e.g. `1d6+1` and `2d6+2` into
+    /// `3d6+3`. Rolls that keep or drop dice cannot be combined.
+    pub(crate) fn merge(&self, other: &Roll) -> Option<Roll> {
+        let base = |roll: &Roll| Roll {
+            num: 0,
+            modifier: None,
+            ..roll.clone()
+        };
+        if self.keep.is_some() || base(self) != base(other) {
+            return None;
+        }
+        let mut merged = self.clone();
+        merged.num = self.num.checked_add(other.num)?;
+        merged.modifier = match (self.modifier, other.modifier) {
+            (Some(a), Some(b)) => Some(a.checked_add(b)?),
+            (a, b) => a.or(b),
+        };
+        Some(merged)
+    }
+
+    /// Returns this roll with its number of dice doubled, as for a critical hit. The count
+    /// saturates rather than overflowing, so huge rolls are left for limits to reject.
+    pub fn critical(&self) -> Roll {
+        let mut roll = self.clone();
+        roll.num = roll.num.saturating_mul(2);
+        roll
+    }
+
+    /// Returns the probability of rolling each face of the die, starting from 1.
+    fn face_probabilities(&self) -> Vec<f64> {
+        match &self.weights {
+            Some(weights) => {
+                let total = weights.iter().sum::<f64>();
+                weights.iter().map(|weight| weight / total).collect()
+            }
+            None => vec![1.0 / self.die as f64; self.die as usize],
         }
     }
 
     fn base_roll(&self, mut rng: impl Rng) -> u32 {
-        rng.gen_range(0..self.die) + 1
+        match &self.weights {
+            Some(weights) => {
+                // Pick a point along the total weight and find the face it lands on. Parsing
+                // ensures the total is finite and positive.
+                let total = weights.iter().sum::<f64>();
+                let mut point = rng.gen_range(0.0..total);
+                let mut landed = weights
+                    .iter()
+                    .rposition(|&weight| weight > 0.0)
+                    .unwrap_or(0)
+                    + 1;
+                for (face, &weight) in weights.iter().enumerate() {
+                    if point < weight {
+                        landed = face + 1;
+                        break;
+                    }
+                    point -= weight;
+                }
+                landed as u32
+            }
+            None => rng.gen_range(0..self.die) + 1,
+        }
     }
 
+    /// Computes the expected total of the roll.
     pub fn expected_total(&self) -> f64 {
-        let num_dice = self
-            .keep
-            .as_ref()
-            .map(|keep| match keep {
-                Keep::High(n) => *n,
-                Keep::Low(n) => *n,
-            })
-            .unwrap_or(self.num as usize) as f64;
-        expected_roll(self.die, self.reroll) * num_dice + (self.modifier.unwrap_or(0) as f64)
+        // Every mechanic is accounted for by the distribution of a single die. Kept dice are
+        // the highest or lowest of the roll, not a fair sample of it.
+        let num = self.num as usize;
+        let dice = match &self.keep {
+            Some(keep) if keep.range(num) != (0..num) => self.expected_kept(keep.range(num)),
+            _ => {
+                let die = self
+                    .die_distribution()
+                    .iter()
+                    .map(|(&value, &p)| self.score(value) as f64 * p)
+                    .sum::<f64>();
+                die * num as f64
+            }
+        };
+        round_noise(dice + self.modifier.unwrap_or(0) as f64)
     }
 
-    pub fn roll(&self, mut rng: impl Rng) -> Outcome {
+    /// Computes the chance that a kept die lands on a natural 20, as for a critical hit, e.g.
+    /// about 14.3% for the three dice of Elven Accuracy. Only plain rolls of d20s have one.
+    pub fn crit_chance(&self) -> Option<f64> {
+        if !self.is_d20() || self.explode.is_some() {
+            return None;
+        }
+        let num = self.num as usize;
+        let kept = self.keep.as_ref().map_or(0..num, |keep| keep.range(num));
+        if kept.is_empty() {
+            return Some(0.0);
+        }
+        // The highest kept die is a 20 exactly when enough dice are 20 to reach its position
+        let twenty = self.die_distribution().get(&20).copied().unwrap_or(0.0);
+        Some(binomial_tails(num, twenty)[num - kept.end + 1])
+    }
+
+    /// Computes the variance of the roll's total, a measure of how swingy it is.
+    pub fn variance(&self) -> f64 {
+        let num = self.num as usize;
+        let variance = match &self.keep {
+            // Kept dice depend on each other, so they need the distribution of the whole roll
+            Some(keep) if keep.range(num) != (0..num) => variance(&self.distribution()),
+            _ => {
+                let mut scored = BTreeMap::new();
+                for (value, p) in self.die_distribution() {
+                    *scored.entry(self.score(value)).or_insert(0.0) += p;
+                }
+                variance(&scored) * num as f64
+            }
+        };
+        round_noise(variance)
+    }
+
+    /// Computes the standard deviation of the roll's total.
+    pub fn standard_deviation(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    /// Computes the distribution of a single die's value, before any per-die bonus.
+    fn die_distribution(&self) -> BTreeMap<i32, f64> {
+        let probabilities = self.face_probabilities();
+        let mut dist = BTreeMap::new();
+        if self.digits > 0 {
+            // Each digit is worth ten times the one after it
+            dist.insert(0, 1.0);
+            for _ in 0..self.digits {
+                let mut next = BTreeMap::new();
+                for (&value, &p) in &dist {
+                    for (face, q) in probabilities.iter().enumerate() {
+                        *next.entry(value * 10 + face as i32 + 1).or_insert(0.0) += p * q;
+                    }
+                }
+                dist = next;
+            }
+            return dist;
+        }
+
+        // Fate dice read each face as two less than its number
+        let offset = if self.fate { -2 } else { 0 };
+        let reroll = (self.reroll.unwrap_or(0) as usize).min(probabilities.len());
+        let rerolled = probabilities[..reroll].iter().sum::<f64>();
+        for (face, p) in probabilities.iter().enumerate() {
+            let kept = if face < reroll { 0.0 } else { *p };
+            *dist.entry(face as i32 + 1 + offset).or_insert(0.0) += kept + rerolled * p;
+        }
+
+        // A die on a triggering face rolls again, adding the new roll less any penalty.
+        // `pending` holds the chance of each chain total that is still exploding.
+        if let Some(explode) = &self.explode {
+            let penalty = if *explode == Explode::Penetrate { 1 } else { 0 };
+            let triggers: Vec<i32> = dist
+                .keys()
+                .copied()
+                .filter(|&value| self.explodes_on(value))
+                .collect();
+            let mut pending: BTreeMap<i32, f64> = triggers
+                .into_iter()
+                .filter_map(|value| Some((value, dist.remove(&value)?)))
+                .collect();
+            let mut explosions = 0;
+            while pending.values().sum::<f64>() > NEGLIGIBLE && explosions < MAX_EXPLOSIONS {
+                let mut next = BTreeMap::new();
+                for (&chain, &chance) in &pending {
+                    for (face, p) in probabilities.iter().enumerate() {
+                        let face = face as i32 + 1;
+                        let value = chain + face - penalty;
+                        let target = if self.explodes_on(face) {
+                            &mut next
+                        } else {
+                            &mut dist
+                        };
+                        *target.entry(value).or_insert(0.0) += chance * p;
+                    }
+                }
+                pending = next;
+                explosions += 1;
+            }
+            for (chain, chance) in pending {
+                if chance > 0.0 {
+                    *dist.entry(chain).or_insert(0.0) += chance;
+                }
+            }
+        }
+        dist
+    }
+
+    /// Scores a die's value towards the total: the value plus any per-die bonus, or for a
+    /// success pool, 1 for a success and -1 for a failure.
+    fn score(&self, value: i32) -> i32 {
+        let value = value.saturating_add(self.each.unwrap_or(0));
+        match &self.target {
+            Some(target) => {
+                let failed = self
+                    .failure
+                    .as_ref()
+                    .is_some_and(|failure| failure.hits(value));
+                target.hits(value) as i32 - failed as i32
+            }
+            None => value,
+        }
+    }
+
+    /// Computes the expected total of the dice at the `kept` positions once the dice are
+    /// sorted in ascending order. The die at position `i` is at most `v` exactly when more
+    /// than `i` dice are at most `v`, which is a binomial tail.
+    fn expected_kept(&self, kept: Range<usize>) -> f64 {
+        let num = self.num as usize;
+        let mut expected = 0.0;
+        let mut at_most = 0.0;
+        let mut previous = binomial_tails(num, 0.0);
+        for (&value, &p) in &self.die_distribution() {
+            at_most += p;
+            let tails = binomial_tails(num, at_most);
+            let chance = kept
+                .clone()
+                .map(|i| tails[i + 1] - previous[i + 1])
+                .sum::<f64>();
+            expected += self.score(value) as f64 * chance;
+            previous = tails;
+        }
+        expected
+    }
+
+    /// Computes the exact probability of each possible total of the roll. Explosion chains are
+    /// followed until they become vanishingly unlikely.
+    pub fn distribution(&self) -> BTreeMap<i32, f64> {
+        let die = self.die_distribution();
+        let score = |value| self.score(value);
+
+        let num = self.num as usize;
+        let dice = match &self.keep {
+            Some(keep) if keep.range(num) != (0..num) => {
+                kept_distribution(&die, num, keep.range(num), score)
+            }
+            _ => {
+                let mut scored = BTreeMap::new();
+                for (&value, &p) in &die {
+                    *scored.entry(score(value)).or_insert(0.0) += p;
+                }
+                convolve_n(&scored, self.num)
+            }
+        };
+
+        let modifier = self.modifier.unwrap_or(0);
+        dice.into_iter()
+            .filter(|&(_, p)| p > 0.0)
+            .map(|(total, p)| (total.saturating_add(modifier), p))
+            .collect()
+    }
+
+    /// Returns warnings about parts of the roll that are valid but probably mistakes.
+    pub fn lint(&self) -> Vec<String> {
+        let mut warnings = vec![];
+        let num = self.num as usize;
+        match self.keep {
+            Some(Keep::High(n)) | Some(Keep::Low(n)) if n > num => {
+                warnings.push(format!("{} keeps {} dice but only rolls {}", self, n, num))
+            }
+            Some(Keep::High(0)) | Some(Keep::Low(0)) => {
+                warnings.push(format!("{} keeps no dice", self))
+            }
+            Some(Keep::DropHigh(n)) | Some(Keep::DropLow(n)) if n >= num => {
+                warnings.push(format!("{} drops every die", self))
+            }
+            _ => {}
+        }
+
+        // Fate dice are rerolled by face, so their rerolls are not checked
+        match self.reroll {
+            Some(0) => warnings.push(format!("{} can never reroll", self)),
+            Some(reroll) if reroll >= self.die && !self.fate => {
+                warnings.push(format!("{} rerolls every result", self))
+            }
+            _ => {}
+        }
+
+        if self.die == 1 {
+            warnings.push(format!("{} always rolls the same result", self));
+        }
+
+        // Compare the constant against the spread of plain summed dice
+        let modifier = self.modifier.unwrap_or(0).unsigned_abs();
+        let kept = self.keep.as_ref().map_or(num, |keep| keep.range(num).len()) as u32;
+        let spread = kept.saturating_mul(self.die - 1);
+        let plain = !self.fate && !self.percentile && self.digits == 0 && self.target.is_none();
+        if plain && spread > 0 && modifier > spread.saturating_mul(4) {
+            warnings.push(format!(
+                "the constant in {} dwarfs the dice, which vary by only {}",
+                self, spread
+            ));
+        }
+
+        warnings
+    }
+
+    /// Rolls the dice using the given random number generator.
+    pub fn roll(&self, rng: impl Rng) -> Outcome {
+        self.roll_limited(rng, MAX_EXPLOSIONS)
+    }
+
+    /// Returns whether a die landing on `value` explodes: on its trigger if it has one, and
+    /// otherwise on its highest face.
+    fn explodes_on(&self, value: i32) -> bool {
+        match &self.explode_on {
+            Some(trigger) => trigger.hits(value),
+            None => value == self.die as i32,
+        }
+    }
+
+    /// Returns whether this is a plain roll of d20s.
+    pub(crate) fn is_d20(&self) -> bool {
+        self.die == 20 && !self.fate && !self.percentile && self.digits == 0
+    }
+
+    /// Rolls the dice as usual, except that a plain d20 roll takes `value` on every die
+    /// instead, as when taking 10 or taking 20 on a check.
+    pub fn take(&self, value: u32, rng: impl Rng) -> Outcome {
+        self.take_limited(value, rng, MAX_EXPLOSIONS)
+    }
+
+    /// Takes `value` on a plain d20 roll as `take` does, allowing any other dice to explode
+    /// at most `max_explosions` times.
+    pub(crate) fn take_limited(&self, value: u32, rng: impl Rng, max_explosions: usize) -> Outcome {
+        if !self.is_d20() {
+            return self.roll_limited(rng, max_explosions);
+        }
+        Outcome::new(
+            vec![DieRoll::Kept(value); self.num as usize],
+            self.keep.clone(),
+            self.each.unwrap_or(0),
+            self.modifier.unwrap_or(0),
+            self.target.clone(),
+            self.failure.clone(),
+        )
+        .with_faces(self.die)
+        .with_tag(self.tag.clone())
+    }
+
+    /// Rolls the dice, allowing each die to explode at most `max_explosions` times.
+    pub(crate) fn roll_limited(&self, mut rng: impl Rng, max_explosions: usize) -> Outcome {
         let mut rolls = Vec::with_capacity(self.num as usize);
+        let mut capped = false;
 
         // Roll the dice
         for _ in 0..self.num {
+            if self.digits > 0 {
+                let digits = (0..self.digits).map(|_| self.base_roll(&mut rng)).collect();
+                rolls.push(DieRoll::Positional(digits));
+                continue;
+            }
+
             // Check if we need to reroll
             let original_roll = self.base_roll(&mut rng);
             let roll = self
@@ -263,11 +1436,149 @@ impl Roll {
                     }
                 })
                 .unwrap_or_else(|| DieRoll::Kept(original_roll));
+            let roll = match roll {
+                DieRoll::Kept(n) if self.fate => DieRoll::Fate(n),
+                DieRoll::Kept(n) if self.percentile => DieRoll::Percentile(n),
+                roll => roll,
+            };
+
+            // Keep rolling while the die lands on a triggering face
+            let roll = match &self.explode {
+                Some(explode) if self.explodes_on(roll.value()) => {
+                    let mut chain = vec![roll.value() as u32];
+                    let exploding = |chain: &[u32]| self.explodes_on(chain[chain.len() - 1] as i32);
+                    while chain.len() <= max_explosions && exploding(&chain) {
+                        chain.push(self.base_roll(&mut rng));
+                    }
+                    capped |= exploding(&chain);
+                    match explode {
+                        Explode::Add => DieRoll::Exploded(chain),
+                        Explode::Compound => DieRoll::Compounded(chain),
+                        Explode::Penetrate => DieRoll::Penetrated(chain),
+                    }
+                }
+                _ => roll,
+            };
 
             // Add the roll
             rolls.push(roll);
         }
 
-        Outcome::new(rolls, self.keep.clone(), self.modifier.unwrap_or(0))
+        Outcome::new(
+            rolls,
+            self.keep.clone(),
+            self.each.unwrap_or(0),
+            self.modifier.unwrap_or(0),
+            self.target.clone(),
+            self.failure.clone(),
+        )
+        .with_faces(self.die)
+        .with_tag(self.tag.clone())
+        .with_capped(capped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn rejects_text_around_a_roll() {
+        for (input, position) in [("xyz2d6garbage", 0), ("hello 3d6", 0), ("2d20h1r1", 6)] {
+            match input.parse::<Roll>() {
+                Err(RollError::Parse { position: p, .. }) => assert_eq!(p, position, "{}", input),
+                parsed => panic!("{} parsed as {:?}", input, parsed),
+            }
+        }
+    }
+
+    #[test]
+    fn display_parses_back() {
+        let inputs = [
+            "4dF",
+            "4d6h3+2",
+            "2d20dl1",
+            "d20!",
+            "6d6!>=5",
+            "d10!!1",
+            "3d6!!r1",
+            "d%",
+            "6d10>7f1",
+            "3d6[+1 each]-2",
+            "d6{1:1,2:1,3:1,4:1,5:1,6:5}",
+        ];
+        for input in inputs {
+            let roll: Roll = input.parse().unwrap();
+            let reparsed: Roll = roll.to_string().parse().unwrap();
+            assert_eq!(reparsed, roll, "{} displayed as {}", input, roll);
+        }
+    }
+
+    #[test]
+    fn custom_explosion_triggers() {
+        let roll: Roll = "d6!>=5".parse().unwrap();
+        assert!((roll.expected_total() - 5.25).abs() < 1e-9);
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let outcome = roll.roll(&mut rng);
+            let chain = outcome.kept()[0].chain();
+            let (last, exploded) = chain.split_last().unwrap();
+            assert!(exploded.iter().all(|&face| face >= 5));
+            assert!(*last < 5);
+        }
+
+        assert!("d6!>=1".parse::<Roll>().is_err());
+        assert!("d6!7".parse::<Roll>().is_err());
+    }
+
+    #[test]
+    fn weights_must_add_up() {
+        let huge = format!("17{}", "0".repeat(307));
+        let input = format!("d6{{1:{},2:{}}}", huge, huge);
+        assert!(matches!(
+            input.parse::<Roll>(),
+            Err(RollError::InvalidDice(_))
+        ));
+
+        let roll: Roll = "d6{1:1,6:3}".parse().unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let total = roll.roll(&mut rng).total();
+            assert!(total == 1 || total == 6);
+        }
+    }
+
+    #[test]
+    fn crit_chances() {
+        let chances = [
+            ("d20", 0.05),
+            ("2d20h1", 0.0975),
+            ("d20aa+5", 0.142625),
+            ("2d20l1", 0.0025),
+            ("d20r1", 0.0525),
+        ];
+        for (input, expected) in chances {
+            let roll: Roll = input.parse().unwrap();
+            let chance = roll.crit_chance().unwrap();
+            assert!((chance - expected).abs() < 1e-9, "{}: {}", input, chance);
+        }
+        assert_eq!("2d6".parse::<Roll>().unwrap().crit_chance(), None);
+    }
+
+    #[test]
+    fn capped_explosions_are_reported() {
+        let roll: Roll = "10d2!".parse().unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            // With no explosions allowed, every 2 is a chain cut short
+            let outcome = roll.roll_limited(&mut rng, 0);
+            assert_eq!(outcome.capped(), outcome.total() > 10);
+            assert_eq!(
+                outcome.capped(),
+                outcome.to_string().ends_with(" (explosion cap reached)")
+            );
+            assert!(!roll.roll(&mut rng).capped());
+        }
     }
 }
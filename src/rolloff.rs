@@ -0,0 +1,89 @@
+use rand::prelude::*;
+use roll::Expr;
+
+/// Rerolls allowed before a tie is declared, so fixed results like `d1` cannot loop forever.
+const MAX_REROLLS: usize = 100;
+
+struct Participant {
+    name: String,
+    expr: Expr,
+}
+
+/// Parses a participant such as `alice:+3` (added to the base roll) or `bob:2d6+1`.
+fn parse_participant(arg: &str, base: &str) -> Result<Participant, &'static str> {
+    let mut iter = arg.splitn(2, ':');
+    let name = iter.next().unwrap_or("");
+    if name.is_empty() {
+        return Err("Participants must be of the form name:modifier.");
+    }
+    let expr = match iter.next() {
+        Some(expr) if expr.starts_with('+') || expr.starts_with('-') => format!("{}{}", base, expr),
+        Some(expr) if !expr.is_empty() => expr.to_string(),
+        _ => base.to_string(),
+    };
+    Ok(Participant {
+        name: name.to_string(),
+        expr: expr.to_lowercase().parse()?,
+    })
+}
+
+/// Rolls each participant, printing results from highest to lowest, and returns the indices
+/// of those tied for the highest total.
+fn roll_round(participants: &[&Participant], rng: &mut impl Rng) -> Vec<usize> {
+    let mut results: Vec<_> = participants
+        .iter()
+        .enumerate()
+        .map(|(i, participant)| (i, participant.expr.roll(&mut *rng)))
+        .collect();
+    results.sort_by_key(|(_, outcome)| -outcome.total());
+
+    for (i, outcome) in results.iter() {
+        println!("{}: {}", participants[*i].name, outcome);
+    }
+
+    let best = results[0].1.total();
+    results
+        .iter()
+        .filter(|(_, outcome)| outcome.total() == best)
+        .map(|(i, _)| *i)
+        .collect()
+}
+
+/// Rolls off between named participants (`off alice:+3 bob:+1`), rerolling ties for first
+/// place until there is a single winner. The base roll is `d20` unless `--dice` is given.
+pub fn run(mut args: impl Iterator<Item = String>, mut rng: impl Rng) -> Result<(), &'static str> {
+    let mut base = String::from("d20");
+    let mut specs = vec![];
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dice" => base = args.next().ok_or("Missing expression for --dice.")?,
+            _ => specs.push(arg),
+        }
+    }
+
+    let participants = specs
+        .iter()
+        .map(|spec| parse_participant(spec, &base))
+        .collect::<Result<Vec<_>, _>>()?;
+    if participants.len() < 2 {
+        return Err("A roll-off needs at least two participants.");
+    }
+
+    let mut contenders: Vec<_> = participants.iter().collect();
+    for _ in 0..=MAX_REROLLS {
+        let tied = roll_round(&contenders, &mut rng);
+        if tied.len() == 1 {
+            println!("Winner: {}", contenders[tied[0]].name);
+            return Ok(());
+        }
+
+        contenders = tied.into_iter().map(|i| contenders[i]).collect();
+        let names: Vec<_> = contenders
+            .iter()
+            .map(|participant| participant.name.as_str())
+            .collect();
+        println!("Tie between {}; rerolling.", names.join(", "));
+    }
+
+    Err("Still tied after the maximum number of rerolls.")
+}
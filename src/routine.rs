@@ -0,0 +1,92 @@
+use rand::prelude::*;
+use roll::{Expr, Roll};
+
+/// A group of identical attacks, e.g. `3x attack +7 1d8+4`.
+struct Attack {
+    count: u32,
+    name: String,
+    bonus: i32,
+    damage: Expr,
+}
+
+/// Parses an attack such as `3x attack +7 1d8+4`; the count defaults to one.
+fn parse_attack(spec: &str) -> Result<Attack, &'static str> {
+    let mut tokens: Vec<_> = spec.split_whitespace().collect();
+    let count = match tokens.first().and_then(|token| token.strip_suffix('x')) {
+        Some(count) => {
+            let count = count
+                .parse::<u32>()
+                .map_err(|_| "Failed to parse number of attacks.")?;
+            tokens.remove(0);
+            count
+        }
+        None => 1,
+    };
+    if tokens.len() != 3 {
+        return Err("Attacks must be of the form [Nx] name +bonus damage.");
+    }
+    let bonus = tokens[1]
+        .parse::<i32>()
+        .map_err(|_| "Failed to parse attack bonus.")?;
+    let damage = tokens[2].to_lowercase().parse()?;
+    Ok(Attack {
+        count,
+        name: tokens[0].to_string(),
+        bonus,
+        damage,
+    })
+}
+
+/// Resolves a full attack routine against an armor class, e.g.
+/// `routine "2x longsword +7 1d8+4" "dagger +7 1d4+4" --ac 16`. A natural 20 always hits
+/// and doubles the damage dice, and a natural 1 always misses.
+pub fn run(mut args: impl Iterator<Item = String>, mut rng: impl Rng) -> Result<(), &'static str> {
+    let mut ac = None;
+    let mut attacks = vec![];
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ac" => {
+                let value = args.next().ok_or("Missing value for --ac.")?;
+                ac = Some(value.parse::<i32>().map_err(|_| "Failed to parse AC.")?);
+            }
+            spec => attacks.push(parse_attack(spec)?),
+        }
+    }
+    let ac = ac.ok_or("An armor class must be given with --ac.")?;
+    if attacks.is_empty() {
+        return Err("No attacks specified.");
+    }
+
+    let mut total = 0;
+    for attack in attacks.iter() {
+        let to_hit = Roll::new(1, 20, None, None, Some(attack.bonus));
+        for i in 1..=attack.count {
+            let outcome = to_hit.roll(&mut rng);
+            let natural = outcome.total() - attack.bonus;
+            let damage = if natural != 20 && outcome.total() < ac {
+                None
+            } else {
+                attack.damage.roll_damage(natural, &mut rng)
+            };
+            let result = match damage {
+                None => "miss",
+                Some(_) if natural == 20 => "critical hit",
+                Some(_) => "hit",
+            };
+
+            match damage {
+                Some(damage) => {
+                    total += damage.total();
+                    println!(
+                        "{} #{}: {} -> {} for {}",
+                        attack.name, i, outcome, result, damage
+                    );
+                }
+                None => println!("{} #{}: {} -> {}", attack.name, i, outcome, result),
+            }
+        }
+    }
+    println!("Total damage: {}", total);
+
+    Ok(())
+}
@@ -0,0 +1,42 @@
+use rand::prelude::*;
+use roll::Expr;
+use std::f64::consts::FRAC_PI_4;
+
+const DIRECTIONS: [&str; 8] = ["N", "NE", "E", "SE", "S", "SW", "W", "NW"];
+
+/// Rolls a random compass direction and a distance, as used by scatter and deviation rules.
+///
+/// Accepts `--distance <expr>` (defaulting to `2d6`) and `--grid` to additionally print the
+/// offset snapped to whole grid squares.
+pub fn run(mut args: impl Iterator<Item = String>, mut rng: impl Rng) -> Result<(), &'static str> {
+    let mut distance: Expr = "2d6".parse()?;
+    let mut grid = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--distance" => {
+                let expr = args.next().ok_or("Missing expression for --distance.")?;
+                distance = expr.to_lowercase().parse()?;
+            }
+            "--grid" => grid = true,
+            _ => return Err("Unknown argument to scatter."),
+        }
+    }
+
+    let facing = rng.gen_range(0..DIRECTIONS.len());
+    let outcome = distance.roll(&mut rng);
+
+    println!("Direction: {} (d8: {})", DIRECTIONS[facing], facing + 1);
+    println!("Distance: {}", outcome);
+
+    if grid {
+        // North is up; east is right
+        let angle = facing as f64 * FRAC_PI_4;
+        let distance = outcome.total() as f64;
+        let x = (distance * angle.sin()).round() as i32;
+        let y = (distance * angle.cos()).round() as i32;
+        println!("Offset: {:+} x, {:+} y", x, y);
+    }
+
+    Ok(())
+}
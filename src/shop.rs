@@ -0,0 +1,93 @@
+use rand::prelude::*;
+use roll::Roll;
+
+/// A stockable item: its name, how common it is, its base price in gold and the dice rolled
+/// for the quantity in stock.
+type Item = (&'static str, f64, u32, &'static str);
+
+const ALCHEMIST: &[Item] = &[
+    ("potion of healing", 6.0, 50, "1d6"),
+    ("antitoxin", 4.0, 50, "1d4"),
+    ("alchemist's fire", 4.0, 50, "1d4"),
+    ("acid (vial)", 4.0, 25, "1d4"),
+    ("holy water", 2.0, 25, "1d3"),
+    ("perfume", 3.0, 5, "1d6"),
+    ("potion of climbing", 1.0, 180, "1d2"),
+    ("potion of greater healing", 1.0, 150, "1d2"),
+];
+
+const BLACKSMITH: &[Item] = &[
+    ("dagger", 6.0, 2, "2d4"),
+    ("handaxe", 4.0, 5, "1d4"),
+    ("longsword", 4.0, 15, "1d4"),
+    ("shortsword", 4.0, 10, "1d4"),
+    ("warhammer", 2.0, 15, "1d3"),
+    ("chain mail", 2.0, 75, "1d2"),
+    ("shield", 4.0, 10, "1d4"),
+    ("plate armor", 0.5, 1500, "1d1"),
+];
+
+const GENERAL: &[Item] = &[
+    ("rations (1 day)", 6.0, 1, "4d6"),
+    ("torch", 6.0, 1, "3d6"),
+    ("hempen rope (50 ft)", 4.0, 1, "1d6"),
+    ("bedroll", 4.0, 1, "1d4"),
+    ("lantern", 2.0, 5, "1d3"),
+    ("tinderbox", 3.0, 1, "1d6"),
+    ("backpack", 3.0, 2, "1d4"),
+    ("healer's kit", 1.0, 5, "1d3"),
+];
+
+/// Returns the item table for a type of shop.
+fn stock(kind: &str) -> Result<&'static [Item], &'static str> {
+    match kind {
+        "alchemist" => Ok(ALCHEMIST),
+        "blacksmith" => Ok(BLACKSMITH),
+        "general" => Ok(GENERAL),
+        _ => Err("Unknown shop type."),
+    }
+}
+
+/// Returns the number of distinct items stocked by a settlement size.
+fn variety(size: &str) -> Result<usize, &'static str> {
+    match size {
+        "village" => Ok(3),
+        "town" => Ok(5),
+        "city" => Ok(8),
+        _ => Err("Unknown settlement size."),
+    }
+}
+
+/// Generates a shop inventory, e.g. `shop --size town --type alchemist`. Items are drawn by
+/// how common they are, each with a rolled quantity and a price varied by 2d6 from 80% to
+/// 130% of its base price.
+pub fn run(mut args: impl Iterator<Item = String>, mut rng: impl Rng) -> Result<(), &'static str> {
+    let mut size = String::from("town");
+    let mut kind = String::from("general");
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--size" => size = args.next().ok_or("Missing value for --size.")?,
+            "--type" => kind = args.next().ok_or("Missing value for --type.")?,
+            _ => return Err("Unknown argument to shop."),
+        }
+    }
+    let items = stock(&kind.to_lowercase())?;
+    let variety = variety(&size.to_lowercase())?;
+
+    let mut stocked: Vec<_> = items
+        .choose_multiple_weighted(&mut rng, variety, |item| item.1)
+        .map_err(|_| "Invalid shop table weights.")?
+        .collect();
+    stocked.sort_by_key(|item| item.0);
+
+    let variation = Roll::new(2, 6, None, None, None);
+    for (name, _, price, quantity) in stocked {
+        let quantity: Roll = quantity.parse()?;
+        let quantity = quantity.roll(&mut rng).total();
+        let percent = 70 + 5 * variation.roll(&mut rng).total() as u32;
+        let price = (price * percent / 100).max(1);
+        println!("{} x{} @ {} gp", name, quantity, price);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use rand::prelude::*;
+use roll::Expr;
+use std::collections::BTreeMap;
+
+/// The most times `--sim` may roll an expression.
+pub const MAX_SIMULATIONS: usize = 10_000_000;
+
+/// The number of rolls simulated for expressions without an exact distribution.
+const DEFAULT_SAMPLES: usize = 100_000;
+
+/// The percentiles shown in a simulation summary.
+const PERCENTILES: [usize; 5] = [5, 25, 50, 75, 95];
+
+/// Rolls an expression `times` times, returning the totals in ascending order.
+pub fn sample(expr: &Expr, times: usize, mut rng: impl Rng) -> Vec<i32> {
+    let mut totals: Vec<_> = (0..times).map(|_| expr.roll(&mut rng).total()).collect();
+    totals.sort_unstable();
+    totals
+}
+
+/// Returns the total at a percentile of sorted totals, using the nearest rank.
+fn percentile(totals: &[i32], percent: usize) -> i32 {
+    let rank = (percent * totals.len()).div_ceil(100);
+    totals[rank.max(1) - 1]
+}
+
+/// Rolls an expression `times` times and prints the spread of its totals, for checking
+/// homebrew mechanics, e.g. `--sim 100000 4d6h3`.
+pub fn run(expr: &Expr, times: usize, rng: impl Rng) -> Result<(), &'static str> {
+    if !expr.is_numeric() {
+        return Err("Symbol dice have no numeric totals.");
+    }
+    let totals = sample(expr, times, rng);
+    let n = totals.len() as f64;
+    let mean = totals.iter().map(|&total| total as f64).sum::<f64>() / n;
+    let variance = totals
+        .iter()
+        .map(|&total| (total as f64 - mean).powi(2))
+        .sum::<f64>()
+        / n;
+    let middle = totals.len() / 2;
+    let median = if totals.len().is_multiple_of(2) {
+        (totals[middle - 1] as f64 + totals[middle] as f64) / 2.0
+    } else {
+        totals[middle] as f64
+    };
+    let percentiles: Vec<_> = PERCENTILES
+        .iter()
+        .map(|&percent| format!("{}%: {}", percent, percentile(&totals, percent)))
+        .collect();
+
+    println!("{}: {} rolls", expr, times);
+    println!("  min: {}, max: {}", totals[0], totals[totals.len() - 1]);
+    println!(
+        "  mean: {:.2}, median: {}, standard deviation: {:.2}",
+        mean,
+        median,
+        variance.sqrt()
+    );
+    println!("  percentiles: {}", percentiles.join(", "));
+    Ok(())
+}
+
+/// Returns the chance of each total of an expression, along with where the chances came
+/// from. A single roll uses its exact distribution unless `samples` asks for a simulation;
+/// anything else is simulated.
+pub fn chances(
+    expr: &Expr,
+    samples: Option<usize>,
+    rng: impl Rng,
+) -> Result<(BTreeMap<i32, f64>, String), &'static str> {
+    if !expr.is_numeric() {
+        return Err("Symbol dice have no numeric totals.");
+    }
+    Ok(match (expr, samples) {
+        (Expr::Roll(roll), None) => (roll.distribution(), String::from("exact")),
+        (_, samples) => {
+            let times = samples.unwrap_or(DEFAULT_SAMPLES);
+            let mut chances = BTreeMap::new();
+            for total in sample(expr, times, rng) {
+                *chances.entry(total).or_insert(0.0) += 1.0 / times as f64;
+            }
+            (chances, format!("{} rolls", times))
+        }
+    })
+}
+
+/// Adds up the chances of the totals from `low` to `high`. The sum starts from positive zero,
+/// so that an empty range is not shown as `-0.00%`.
+fn chance_between(chances: &BTreeMap<i32, f64>, low: i32, high: i32) -> f64 {
+    chances
+        .range(low..=high)
+        .fold(0.0, |sum, (_, chance)| sum + chance)
+}
+
+/// Prints the chance that an expression's total falls within the given bounds, e.g.
+/// `1d20+5 --at-least 15` for the odds of hitting AC 15.
+pub fn odds(
+    expr: &Expr,
+    at_least: Option<i32>,
+    at_most: Option<i32>,
+    samples: Option<usize>,
+    rng: impl Rng,
+) -> Result<(), &'static str> {
+    let low = at_least.unwrap_or(i32::MIN);
+    let high = at_most.unwrap_or(i32::MAX);
+    if low > high {
+        return Err("The value for --at-least cannot be above --at-most.");
+    }
+    let (chances, source) = chances(expr, samples, rng)?;
+    let chance = chance_between(&chances, low, high);
+    let bounds = match (at_least, at_most) {
+        (Some(low), Some(high)) => format!("between {} and {}", low, high),
+        (Some(low), None) => format!("at least {}", low),
+        (None, Some(high)) => format!("at most {}", high),
+        (None, None) => String::from("anything"),
+    };
+    println!(
+        "{}: {:.2}% to roll {} ({})",
+        expr,
+        chance * 100.0,
+        bounds,
+        source
+    );
+    if let Some(crit) = expr.crit_chance() {
+        println!("  natural 20: {:.2}%", crit * 100.0);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_range_has_no_chance() {
+        let roll: Expr = "1d20+5".parse().unwrap();
+        let (chances, _) = chances(&roll, None, rand::thread_rng()).unwrap();
+        let chance = chance_between(&chances, 30, 40);
+        assert_eq!(format!("{:.2}%", chance * 100.0), "0.00%");
+        assert!((chance_between(&chances, 16, 25) - 0.5).abs() < 1e-9);
+    }
+}
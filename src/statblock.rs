@@ -0,0 +1,113 @@
+use rand::prelude::*;
+use roll::{Expr, Roll};
+use std::fs;
+
+/// A named entry of a statblock, keyed by its section (`attack`, `save` or `ability`).
+struct Entry {
+    section: String,
+    name: String,
+    value: String,
+}
+
+/// Loads the entries of a statblock file made of `[section]` headers followed by
+/// `name = "value"` lines. Blank lines and `#` comments are ignored, so simple TOML files
+/// can be used directly.
+fn load(path: &str) -> Result<Vec<Entry>, &'static str> {
+    let contents = fs::read_to_string(path).map_err(|_| "Failed to read statblock file.")?;
+    let mut section = String::new();
+    let mut entries = vec![];
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            section = header.trim_end_matches(']').trim().to_lowercase();
+            continue;
+        }
+        let mut iter = line.splitn(2, '=');
+        let name = iter.next().unwrap_or("").trim();
+        let value = iter
+            .next()
+            .ok_or("Statblock lines must be of the form name = \"value\".")?;
+        entries.push(Entry {
+            section: section.clone(),
+            name: name.to_lowercase(),
+            value: value.trim().trim_matches('"').to_lowercase(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Rolls an attack written as `+bonus damage`, e.g. `+6 2d8+4`, against an optional armor
+/// class. A natural 20 is a critical hit that doubles the damage dice, and a natural 1 always
+/// misses. Without an armor class, other attacks report the highest AC they would hit.
+fn attack(name: &str, value: &str, ac: Option<i32>, mut rng: impl Rng) -> Result<(), &'static str> {
+    let tokens: Vec<_> = value.split_whitespace().collect();
+    if tokens.len() != 2 {
+        return Err("Attacks must be of the form \"+bonus damage\".");
+    }
+    let bonus = tokens[0]
+        .parse::<i32>()
+        .map_err(|_| "Failed to parse attack bonus.")?;
+    let damage: Expr = tokens[1].parse()?;
+
+    let outcome = Roll::new(1, 20, None, None, Some(bonus)).roll(&mut rng);
+    let natural = outcome.total() - bonus;
+    let damage = match ac {
+        Some(ac) if natural != 20 && outcome.total() < ac => None,
+        _ => damage.roll_damage(natural, &mut rng),
+    };
+    let result = match (&damage, ac) {
+        (None, _) => String::from("miss"),
+        (Some(_), _) if natural == 20 => String::from("critical hit"),
+        (Some(_), Some(_)) => String::from("hit"),
+        (Some(_), None) => format!("hits AC {} or lower", outcome.total()),
+    };
+    match damage {
+        Some(damage) => println!("{}: {} -> {} for {}", name, outcome, result, damage),
+        None => println!("{}: {} -> {}", name, outcome, result),
+    }
+    Ok(())
+}
+
+/// Resolves a named entry of a statblock file, e.g. `statblock ogre.toml attack greatclub`.
+/// Attacks roll to hit, against `--ac` if given, and then damage; saves and abilities roll
+/// their expression.
+pub fn run(mut args: impl Iterator<Item = String>, mut rng: impl Rng) -> Result<(), &'static str> {
+    let path = args.next().ok_or("Missing statblock file.")?;
+    let section = args
+        .next()
+        .ok_or("Missing statblock section.")?
+        .to_lowercase();
+    let name = args
+        .next()
+        .ok_or("Missing statblock entry.")?
+        .to_lowercase();
+    let mut ac = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ac" => {
+                let value = args.next().ok_or("Missing value for --ac.")?;
+                ac = Some(value.parse::<i32>().map_err(|_| "Failed to parse AC.")?);
+            }
+            _ => return Err("Unknown argument to statblock."),
+        }
+    }
+
+    let entries = load(&path)?;
+    let entry = entries
+        .iter()
+        .find(|entry| entry.section == section && entry.name == name)
+        .ok_or("Unknown statblock entry.")?;
+
+    match entry.section.as_str() {
+        "attack" => attack(&entry.name, &entry.value, ac, rng),
+        "save" | "ability" => {
+            let expr: Expr = entry.value.parse()?;
+            println!("{}: {}", entry.name, expr.roll(&mut rng));
+            Ok(())
+        }
+        _ => Err("Unknown statblock section."),
+    }
+}
@@ -0,0 +1,180 @@
+use rand::prelude::*;
+use roll::Expr;
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+};
+
+const ABILITIES: [&str; 6] = ["STR", "DEX", "CON", "INT", "WIS", "CHA"];
+
+/// Attempts allowed before giving up on a policy that may be impossible to satisfy.
+const MAX_ATTEMPTS: usize = 10_000;
+
+/// A minimum that a rolled array must reach to be accepted.
+#[derive(Clone, Copy, Debug)]
+enum Rule {
+    Total(i32),
+    Highest(i32),
+    Lowest(i32),
+}
+
+impl Rule {
+    /// Parses a `--reroll-if` condition such as `no score above 13`, `any score below 8` or
+    /// `total below 70` into the rule that an accepted array must satisfy.
+    fn parse_reroll_if(condition: &str) -> Result<Rule, &'static str> {
+        let words: Vec<_> = condition.split_whitespace().collect();
+        let (phrase, value) = words.split_at(words.len().saturating_sub(1));
+        let value = value
+            .first()
+            .and_then(|value| value.parse::<i32>().ok())
+            .ok_or("Reroll conditions must end with a number.")?;
+        match phrase.join(" ").to_lowercase().as_str() {
+            "no score above" => Ok(Rule::Highest(value + 1)),
+            "any score below" => Ok(Rule::Lowest(value)),
+            "total below" => Ok(Rule::Total(value)),
+            _ => Err("Unknown reroll condition."),
+        }
+    }
+
+    fn passes(&self, scores: &[i32]) -> bool {
+        match *self {
+            Rule::Total(n) => scores.iter().sum::<i32>() >= n,
+            Rule::Highest(n) => scores.iter().max().is_some_and(|&max| max >= n),
+            Rule::Lowest(n) => scores.iter().min().is_some_and(|&min| min >= n),
+        }
+    }
+}
+
+fn parse_number(arg: Option<String>, name: &'static str) -> Result<i32, &'static str> {
+    arg.ok_or(name)?
+        .parse()
+        .map_err(|_| "Failed to parse stats policy value.")
+}
+
+fn format_scores(scores: &[i32]) -> String {
+    let scores: Vec<_> = scores.iter().map(|score| score.to_string()).collect();
+    scores.join(", ")
+}
+
+/// Prompts for which rolled score to assign to each ability, in order.
+fn assign(mut scores: Vec<i32>) -> Result<Vec<(&'static str, i32)>, &'static str> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut assigned = vec![];
+
+    for ability in ABILITIES.iter() {
+        loop {
+            // Only one score is left for the last ability
+            if scores.len() == 1 {
+                assigned.push((*ability, scores.remove(0)));
+                break;
+            }
+
+            print!("{} [{}]: ", ability, format_scores(&scores));
+            io::stdout()
+                .flush()
+                .map_err(|_| "Failed to write prompt.")?;
+            let line = lines
+                .next()
+                .ok_or("Assignment cancelled.")?
+                .map_err(|_| "Failed to read input.")?;
+            match line.trim().parse::<i32>() {
+                Ok(score) => match scores.iter().position(|&s| s == score) {
+                    Some(i) => {
+                        assigned.push((*ability, scores.remove(i)));
+                        break;
+                    }
+                    None => println!("{} is not one of the remaining scores.", score),
+                },
+                Err(_) => println!("Enter one of the remaining scores."),
+            }
+        }
+    }
+
+    Ok(assigned)
+}
+
+/// Rolls a set of ability scores using the given expressions, rerolling the whole array until
+/// it passes any `--min-total`, `--min-highest` or `--reroll-if` policies. With `--assign`,
+/// prompts for which score goes to each ability, optionally writing the result to
+/// `--out <file>` as `ability = score` lines.
+pub fn run(
+    exprs: &[Expr],
+    mut args: impl Iterator<Item = String>,
+    mut rng: impl Rng,
+) -> Result<(), &'static str> {
+    let mut interactive = false;
+    let mut out = None;
+    let mut rules = vec![];
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--assign" => interactive = true,
+            "--min-total" => rules.push(Rule::Total(parse_number(
+                args.next(),
+                "Missing value for --min-total.",
+            )?)),
+            "--min-highest" => rules.push(Rule::Highest(parse_number(
+                args.next(),
+                "Missing value for --min-highest.",
+            )?)),
+            "--reroll-if" => {
+                let condition = args.next().ok_or("Missing condition for --reroll-if.")?;
+                rules.push(Rule::parse_reroll_if(&condition)?);
+            }
+            "--out" => out = Some(args.next().ok_or("Missing file for --out.")?),
+            _ => return Err("Unknown argument to stats."),
+        }
+    }
+    if exprs.len() != ABILITIES.len() {
+        return Err("The stats macro must roll exactly six scores.");
+    }
+
+    let mut attempts = 0;
+    let scores = loop {
+        if attempts == MAX_ATTEMPTS {
+            return Err("No array satisfied the stats policy; it may be impossible.");
+        }
+        attempts += 1;
+
+        let mut scores: Vec<_> = exprs
+            .iter()
+            .map(|expr| expr.roll(&mut rng).total())
+            .collect();
+        scores.sort_unstable_by(|a, b| b.cmp(a));
+        if rules.iter().all(|rule| rule.passes(&scores)) {
+            break scores;
+        }
+    };
+
+    if rules.is_empty() {
+        println!("Scores: {}", format_scores(&scores));
+    } else {
+        let plural = if attempts == 1 { "" } else { "s" };
+        println!(
+            "Scores: {} (total {}, {} attempt{})",
+            format_scores(&scores),
+            scores.iter().sum::<i32>(),
+            attempts,
+            plural
+        );
+    }
+
+    if !interactive {
+        return Ok(());
+    }
+
+    let assigned = assign(scores)?;
+    let lines: Vec<_> = assigned
+        .iter()
+        .map(|(ability, score)| format!("{} = {}", ability.to_lowercase(), score))
+        .collect();
+    for line in lines.iter() {
+        println!("{}", line);
+    }
+    if let Some(path) = out {
+        fs::write(&path, lines.join("\n") + "\n").map_err(|_| "Failed to write profile.")?;
+        println!("Wrote {}", path);
+    }
+
+    Ok(())
+}
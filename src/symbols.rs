@@ -0,0 +1,99 @@
+use crate::error::RollError;
+use rand::prelude::*;
+use regex::Regex;
+use std::{fmt, str};
+
+lazy_static! {
+    static ref SYMBOL_REGEX: Regex =
+        Regex::new(r"^(?P<num>[0-9]*)d\[(?P<faces>[^\]]+)\]$").unwrap();
+}
+
+/// A roll of dice whose faces are arbitrary symbols, e.g. `3d[skull,shield,blank]`.
+#[derive(Clone, Debug)]
+pub struct SymbolRoll {
+    num: u32,
+    faces: Vec<String>,
+}
+
+/// The faces rolled by a `SymbolRoll`, reported as a tally per symbol.
+#[derive(Clone, Debug)]
+pub struct SymbolOutcome {
+    rolls: Vec<String>,
+}
+
+impl fmt::Display for SymbolRoll {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.num > 1 {
+            write!(f, "{}", self.num)?;
+        }
+        write!(f, "d[{}]", self.faces.join(","))
+    }
+}
+
+impl fmt::Display for SymbolOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tally: Vec<_> = self
+            .tally()
+            .iter()
+            .map(|(symbol, count)| format!("{} ×{}", symbol, count))
+            .collect();
+        write!(f, "{} ({})", tally.join(", "), self.rolls.join(", "))
+    }
+}
+
+impl SymbolOutcome {
+    /// Counts each rolled symbol, in the order the symbols first appeared.
+    pub fn tally(&self) -> Vec<(&str, usize)> {
+        let mut tally: Vec<(&str, usize)> = vec![];
+        for roll in self.rolls.iter() {
+            match tally.iter_mut().find(|(symbol, _)| symbol == roll) {
+                Some((_, count)) => *count += 1,
+                None => tally.push((roll, 1)),
+            }
+        }
+        tally
+    }
+}
+
+impl str::FromStr for SymbolRoll {
+    type Err = RollError;
+
+    fn from_str(input: &str) -> Result<SymbolRoll, Self::Err> {
+        let cap = SYMBOL_REGEX
+            .captures(input)
+            .ok_or_else(|| RollError::parse(input, 0, "Invalid symbol dice notation."))?;
+        let num = match &cap["num"] {
+            "" => 1,
+            num => num
+                .parse::<u32>()
+                .map_err(|_| RollError::parse(input, 0, "Failed to parse number of dice."))?,
+        };
+        let faces: Vec<String> = cap["faces"]
+            .split(',')
+            .map(|face| face.trim().to_string())
+            .collect();
+        if faces.iter().any(|face| face.is_empty()) {
+            return Err(RollError::InvalidDice("Symbol dice faces cannot be empty."));
+        }
+        Ok(SymbolRoll { num, faces })
+    }
+}
+
+impl SymbolRoll {
+    pub(crate) fn num(&self) -> u32 {
+        self.num
+    }
+
+    /// Returns whether the input looks like symbol dice notation.
+    pub fn matches(input: &str) -> bool {
+        SYMBOL_REGEX.is_match(input)
+    }
+
+    /// Rolls the dice using the given random number generator.
+    pub fn roll(&self, mut rng: impl Rng) -> SymbolOutcome {
+        let rolls = (0..self.num)
+            .map(|_| self.faces.choose(&mut rng).unwrap().clone())
+            .collect();
+        SymbolOutcome { rolls }
+    }
+}
@@ -0,0 +1,52 @@
+const DEFAULT_LABELS: [&[&str]; 3] = [
+    &["failure", "success"],
+    &["failure", "partial", "success"],
+    &["failure", "partial", "success", "critical"],
+];
+
+/// A set of ascending thresholds dividing results into labelled degrees of success.
+#[derive(Clone, Debug)]
+pub struct Tiers {
+    thresholds: Vec<i32>,
+    labels: Vec<String>,
+}
+
+impl Tiers {
+    /// Parses thresholds such as `10/15/20`, with optional labels such as
+    /// `miss/graze/hit/crit`. There must be one more label than thresholds.
+    pub fn parse(thresholds: &str, labels: Option<&str>) -> Result<Tiers, &'static str> {
+        let thresholds = thresholds
+            .split('/')
+            .map(|threshold| threshold.parse::<i32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| "Failed to parse tier thresholds.")?;
+        if thresholds.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err("Tier thresholds must be ascending.");
+        }
+
+        let labels: Vec<String> = match labels {
+            Some(labels) => labels.split('/').map(String::from).collect(),
+            None => match DEFAULT_LABELS.get(thresholds.len() - 1) {
+                Some(labels) => labels.iter().map(|label| label.to_string()).collect(),
+                None => (0..=thresholds.len())
+                    .map(|tier| format!("tier {}", tier))
+                    .collect(),
+            },
+        };
+        if labels.len() != thresholds.len() + 1 {
+            return Err("There must be exactly one more tier label than thresholds.");
+        }
+
+        Ok(Tiers { thresholds, labels })
+    }
+
+    /// Returns the label of the highest tier reached by the given total.
+    pub fn label(&self, total: i32) -> &str {
+        let tier = self
+            .thresholds
+            .iter()
+            .take_while(|&&threshold| total >= threshold)
+            .count();
+        &self.labels[tier]
+    }
+}